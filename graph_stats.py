@@ -14,8 +14,8 @@ def median(lst):
 
 def graph_blocks_vs_time(dataset):
     for data in dataset.values():
-        times = [x[1] for x in data]
-        block_counts = [x[2] + x[3] + x[4] + x[5] for x in data]
+        times = [x[2] + x[3] + x[4] + x[5] for x in data]
+        block_counts = [x[1] for x in data]
         plt.plot(block_counts, times, 'o')
     plt.show()
     #raise NotImplementedError
@@ -30,11 +30,25 @@ def graph_funcs_vs_time(dataset):
         plt.plot(func_count, total_times, 'o')
     plt.show()
 
+# flatten a report written by `veriwasm -o` into
+# (name, blocks, cfg, stack, heap, call) rows, skipping functions that errored
+def report_rows(report):
+    rows = []
+    for func in report["functions"]:
+        if func["verdict"] == "internal_error":
+            continue
+        t = func["timings"]
+        rows.append((func["name"], func["num_blocks"], t["cfg_secs"], t["stack_secs"], t["heap_secs"], t["call_secs"]))
+    return rows
+
 def get_data(filenames):
     dataset = {}
     for filename in filenames:
         with open(filename) as f:
             data = json.load(f)
+        # older stats files are already a list of rows
+        if isinstance(data, dict):
+            data = report_rows(data)
         dataset[filename] = data
     return dataset
 
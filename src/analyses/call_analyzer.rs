@@ -6,6 +6,7 @@ use crate::lattices::davlattice::DAV;
 use crate::lattices::reachingdefslattice::{LocIdx, ReachLattice};
 use crate::lattices::stacklattice::StackSlot;
 use crate::lattices::VarState;
+use crate::profile::TableLayout;
 use crate::utils::ir_utils::{extract_stack_offset, is_stack_access};
 use crate::utils::lifter::IRBlock;
 use crate::utils::lifter::{Binopcode, IRMap, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value};
@@ -210,42 +211,56 @@ impl AbstractAnalyzer<CallCheckLattice> for CallAnalyzer {
     }
 }
 
-// mem[LucetTableBase + 8]
-pub fn is_table_size(in_state: &CallCheckLattice, memargs: &MemArgs) -> bool {
-    if let MemArgs::Mem2Args(MemArg::Reg(regnum1, size), MemArg::Imm(_, _, 8)) = memargs {
+// mem[LucetTableBase + size offset]
+pub fn is_table_size(in_state: &CallCheckLattice, memargs: &MemArgs, tables: &TableLayout) -> bool {
+    if let MemArgs::Mem2Args(MemArg::Reg(regnum1, size), MemArg::Imm(_, _, offset)) = memargs {
         if let Some(CallCheckValue::LucetTablesBase) = in_state.regs.get(regnum1, size).v {
-            return true;
+            return *offset == tables.size_offset;
         }
     }
     false
 }
 
-pub fn is_fn_ptr(in_state: &CallCheckLattice, memargs: &MemArgs) -> bool {
-    if let MemArgs::Mem3Args(
-        MemArg::Reg(regnum1, size1),
-        MemArg::Reg(regnum2, size2),
-        MemArg::Imm(_, _, immval),
-    ) = memargs
-    {
-        match (
-            in_state.regs.get(regnum1, size1).v,
-            in_state.regs.get(regnum2, size2).v,
-            immval,
-        ) {
-            (
-                Some(CallCheckValue::GuestTableBase),
-                Some(CallCheckValue::PtrOffset(DAV::Checked)),
-                8,
-            ) => return true,
-            (
-                Some(CallCheckValue::PtrOffset(DAV::Checked)),
-                Some(CallCheckValue::GuestTableBase),
-                8,
-            ) => return true,
-            _ => return false,
+// mem[GuestTableBase + checked offset + fn ptr offset], with the checked index
+// either shifted by entry_shift already or scaled by the operand
+pub fn is_fn_ptr(in_state: &CallCheckLattice, memargs: &MemArgs, tables: &TableLayout) -> bool {
+    match memargs {
+        MemArgs::Mem3Args(
+            MemArg::Reg(regnum1, size1),
+            MemArg::Reg(regnum2, size2),
+            MemArg::Imm(_, _, immval),
+        ) => {
+            if *immval != tables.fn_ptr_offset {
+                return false;
+            }
+            match (
+                in_state.regs.get(regnum1, size1).v,
+                in_state.regs.get(regnum2, size2).v,
+            ) {
+                (
+                    Some(CallCheckValue::GuestTableBase),
+                    Some(CallCheckValue::PtrOffset(DAV::Checked)),
+                ) => true,
+                (
+                    Some(CallCheckValue::PtrOffset(DAV::Checked)),
+                    Some(CallCheckValue::GuestTableBase),
+                ) => true,
+                _ => false,
+            }
+        }
+        MemArgs::MemScaleDisp(
+            MemArg::Reg(regnum1, size1),
+            MemArg::Reg(regnum2, size2),
+            MemArg::Imm(_, _, scale),
+            MemArg::Imm(_, _, immval),
+        ) => {
+            *scale == 1 << tables.entry_shift
+                && *immval == tables.fn_ptr_offset
+                && in_state.regs.get(regnum1, size1).v == Some(CallCheckValue::GuestTableBase)
+                && in_state.regs.get(regnum2, size2).v == Some(CallCheckValue::CheckedVal)
         }
+        _ => false,
     }
-    false
 }
 
 impl CallAnalyzer {
@@ -256,28 +271,29 @@ impl CallAnalyzer {
     pub fn aeval_unop(&self, in_state: &CallCheckLattice, value: &Value) -> CallCheckValueLattice {
         match value {
             Value::Mem(memsize, memargs) => {
-                if is_table_size(in_state, memargs) {
+                if is_table_size(in_state, memargs, &self.metadata.tables) {
                     return CallCheckValueLattice {
                         v: Some(CallCheckValue::TableSize),
                     };
-                } else if is_fn_ptr(in_state, memargs) {
+                } else if is_fn_ptr(in_state, memargs, &self.metadata.tables) {
                     return CallCheckValueLattice {
                         v: Some(CallCheckValue::FnPtr),
                     };
                 } else if is_stack_access(value) {
-                    let offset = extract_stack_offset(memargs);
-                    return in_state.stack.get(offset, memsize.to_u32() / 8);
+                    if let Some(offset) = extract_stack_offset(memargs) {
+                        return in_state.stack.get(offset, memsize.to_u32() / 8);
+                    }
                 }
             }
 
             Value::Reg(regnum, size) => return in_state.regs.get(regnum, size),
 
             Value::Imm(_, _, immval) => {
-                if (*immval as u64) == self.metadata.guest_table_0 {
+                if Some(*immval as u64) == self.metadata.guest_table_0 {
                     return CallCheckValueLattice {
                         v: Some(CallCheckValue::GuestTableBase),
                     };
-                } else if (*immval as u64) == self.metadata.lucet_tables {
+                } else if Some(*immval as u64) == self.metadata.lucet_tables {
                     return CallCheckValueLattice {
                         v: Some(CallCheckValue::LucetTablesBase),
                     };
@@ -298,7 +314,7 @@ impl CallAnalyzer {
         Default::default()
     }
 
-    //checked_val << 4
+    //checked_val << entry_shift
     pub fn aeval_binop(
         &self,
         in_state: &CallCheckLattice,
@@ -308,7 +324,10 @@ impl CallAnalyzer {
         loc_idx: &LocIdx,
     ) -> CallCheckValueLattice {
         if let Binopcode::Shl = opcode {
-            if let (Value::Reg(regnum1, size1), Value::Imm(_, _, 4)) = (src1, src2) {
+            if let (Value::Reg(regnum1, size1), Value::Imm(_, _, shift)) = (src1, src2) {
+                if *shift != self.metadata.tables.entry_shift {
+                    return Default::default();
+                }
                 if let Some(CallCheckValue::CheckedVal) = in_state.regs.get(regnum1, size1).v {
                     return CallCheckValueLattice {
                         v: Some(CallCheckValue::PtrOffset(DAV::Checked)),
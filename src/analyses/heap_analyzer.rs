@@ -1,23 +1,134 @@
 use crate::analyses::AbstractAnalyzer;
-use crate::lattices::heaplattice::{HeapLattice, HeapValue, HeapValueLattice};
+use crate::lattices::heaplattice::{HeapLattice, HeapValue, HeapValueLattice, MemoryIdx};
 use crate::lattices::reachingdefslattice::LocIdx;
 use crate::lattices::{ConstLattice, VarState};
 use crate::utils::ir_utils::{extract_stack_offset, is_stack_access};
-use crate::utils::lifter::{Binopcode, MemArg, MemArgs, Unopcode, ValSize, Value};
+use crate::utils::lifter::{Binopcode, IRMap, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value};
 use crate::utils::utils::LucetMetadata;
+use crate::{HeapStrategy, VMCtxMemory};
 use std::default::Default;
+use yaxpeax_x86::long_mode::Opcode;
 
 pub struct HeapAnalyzer {
     pub metadata: LucetMetadata,
+    pub strategy: HeapStrategy,
 }
 
 impl AbstractAnalyzer<HeapLattice> for HeapAnalyzer {
     fn init_state(&self) -> HeapLattice {
         let mut result: HeapLattice = Default::default();
-        result.regs.rdi = HeapValueLattice::new(HeapValue::HeapBase);
+        match self.strategy {
+            HeapStrategy::HeapPtrFirstArgWithGuards => {
+                result.regs.rdi = HeapValueLattice::new(HeapValue::HeapBase(0));
+            }
+            _ => {
+                result.regs.rdi = HeapValueLattice::new(HeapValue::VMCtxPtr);
+            }
+        }
         result
     }
 
+    fn aexec(&self, in_state: &mut HeapLattice, ir_instr: &Stmt, loc_idx: &LocIdx) -> () {
+        // A bounds check, or a register plus a constant, only tells us about
+        // the value the register held at the time.
+        for regnum in 0..17 {
+            match in_state.regs.get(&regnum, &ValSize::Size64).v {
+                Some(HeapValue::BoundsCheckFlag(reg, _, _))
+                | Some(HeapValue::IndexPlus(reg, _))
+                | Some(HeapValue::WrappingIndexPlus(reg, _))
+                | Some(HeapValue::CarryFlag(reg))
+                    if clobbers_reg(ir_instr, reg) =>
+                {
+                    in_state
+                        .regs
+                        .set(&regnum, &ValSize::Size64, Default::default());
+                }
+                _ => (),
+            }
+        }
+        match ir_instr {
+            // the flags of `add reg, c`, which is lifted as the add followed
+            // by this clear
+            Stmt::Clear(Value::Reg(16, _), srcs) if self.is_add_carry(in_state, srcs) => {
+                if let Value::Reg(regnum, _) = srcs[0] {
+                    in_state.regs.zf = HeapValueLattice::new(HeapValue::CarryFlag(regnum));
+                }
+            }
+            Stmt::Clear(dst, _srcs) => in_state.set_to_bot(dst),
+            Stmt::Unop(opcode, dst, src) => self.aexec_unop(in_state, opcode, &dst, &src, loc_idx),
+            Stmt::Binop(opcode, dst, src1, src2) => {
+                self.aexec_binop(in_state, opcode, dst, src1, src2, loc_idx);
+                in_state.adjust_stack_offset(opcode, dst, src1, src2)
+            }
+            Stmt::Call(_) => in_state.on_call(),
+            _ => (),
+        }
+    }
+
+    fn process_branch(
+        &self,
+        irmap: &IRMap,
+        in_state: &HeapLattice,
+        succ_addrs: &Vec<u64>,
+        addr: &u64,
+    ) -> Vec<(u64, HeapLattice)> {
+        let br_stmt = irmap
+            .get(addr)
+            .and_then(|block| block.last())
+            .and_then(|(_, stmts)| stmts.last());
+        let br_opcode = match br_stmt {
+            Some(Stmt::Branch(opcode, _)) if succ_addrs.len() == 2 => Some(*opcode),
+            _ => None,
+        };
+        match (br_opcode, in_state.regs.zf.v) {
+            (Some(opcode), Some(HeapValue::BoundsCheckFlag(regnum, memory, offset))) => {
+                // (whether the branch is taken when the index is in bounds, and
+                // the bytes past index + c that the check proves are in bounds:
+                // one for idx + c < bound, none for idx + c <= bound)
+                let check = match opcode {
+                    Opcode::JB => Some((true, 1)),
+                    Opcode::JNB => Some((false, 1)),
+                    Opcode::JNA => Some((true, 0)),
+                    Opcode::JA => Some((false, 0)),
+                    _ => None,
+                };
+                if let Some((in_bounds_if_taken, strict)) = check {
+                    log::debug!(
+                        "branch at 0x{:x}: bounds check of reg {} for memory {}",
+                        addr,
+                        regnum,
+                        memory
+                    );
+                    let checked = HeapValue::CheckedIndex(memory, offset + strict);
+                    return branch_states(in_state, succ_addrs, in_bounds_if_taken, |state| {
+                        state
+                            .regs
+                            .set(&regnum, &ValSize::Size64, HeapValueLattice::new(checked))
+                    });
+                }
+            }
+            // jb/jnb after `add reg, c`: without a carry, reg = index + c
+            (Some(opcode @ Opcode::JB), Some(HeapValue::CarryFlag(regnum)))
+            | (Some(opcode @ Opcode::JNB), Some(HeapValue::CarryFlag(regnum))) => {
+                if let Some(HeapValue::WrappingIndexPlus(index, offset)) =
+                    in_state.regs.get(&regnum, &ValSize::Size64).v
+                {
+                    let exact = HeapValue::IndexPlus(index, offset);
+                    return branch_states(in_state, succ_addrs, opcode == Opcode::JNB, |state| {
+                        state
+                            .regs
+                            .set(&regnum, &ValSize::Size64, HeapValueLattice::new(exact))
+                    });
+                }
+            }
+            _ => (),
+        }
+        succ_addrs
+            .into_iter()
+            .map(|addr| (addr.clone(), in_state.clone()))
+            .collect()
+    }
+
     fn aexec_unop(
         &self,
         in_state: &mut HeapLattice,
@@ -42,6 +153,27 @@ impl AbstractAnalyzer<HeapLattice> for HeapAnalyzer {
         match opcode {
             Unopcode::Mov => {
                 let v = self.aeval_unop(in_state, src);
+                // mov rd, rs of a 64-bit index, e.g. before adding the
+                // access offset to it
+                if let (&Value::Reg(rd, ValSize::Size64), &Value::Reg(rs, ValSize::Size64), None) =
+                    (dst, src, &v.v)
+                {
+                    if rd != rs && rs != 4 {
+                        in_state.regs.set(
+                            &rd,
+                            &ValSize::Size64,
+                            HeapValueLattice::new(HeapValue::IndexPlus(rs, 0)),
+                        );
+                        return;
+                    }
+                }
+                // values relative to a register are only tracked in registers
+                if let (Value::Mem(..), Some(HeapValue::IndexPlus(..)))
+                | (Value::Mem(..), Some(HeapValue::WrappingIndexPlus(..))) = (dst, &v.v)
+                {
+                    in_state.set_to_bot(dst);
+                    return;
+                }
                 in_state.set(dst, v);
             }
             Unopcode::Movsx => {
@@ -60,6 +192,11 @@ impl AbstractAnalyzer<HeapLattice> for HeapAnalyzer {
         _loc_idx: &LocIdx,
     ) {
         match opcode {
+            // compares only write flags
+            Binopcode::Cmp | Binopcode::Test => {
+                in_state.regs.zf = self.aeval_cmp(in_state, opcode, src1, src2);
+                return;
+            }
             Binopcode::Add => {
                 if let (
                     &Value::Reg(rd, ValSize::Size64),
@@ -70,20 +207,71 @@ impl AbstractAnalyzer<HeapLattice> for HeapAnalyzer {
                     let rs1_val = in_state.regs.get(&rs1, &ValSize::Size64).v;
                     let rs2_val = in_state.regs.get(&rs2, &ValSize::Size64).v;
                     match (rs1_val, rs2_val) {
-                        (Some(HeapValue::HeapBase), Some(HeapValue::Bounded4GB))
-                        | (Some(HeapValue::Bounded4GB), Some(HeapValue::HeapBase)) => {
-                            in_state.regs.set(
-                                &rd,
-                                &ValSize::Size64,
-                                ConstLattice {
-                                    v: Some(HeapValue::HeapAddr),
-                                },
-                            );
+                        (Some(HeapValue::HeapBase(memory)), Some(v))
+                        | (Some(v), Some(HeapValue::HeapBase(memory)))
+                            if self.is_bounded_index(memory, v) =>
+                        {
+                            let addr = match v {
+                                HeapValue::CheckedIndex(_, bytes) => {
+                                    HeapValue::CheckedAddr(memory, bytes)
+                                }
+                                _ => HeapValue::HeapAddr(memory),
+                            };
+                            in_state
+                                .regs
+                                .set(&rd, &ValSize::Size64, HeapValueLattice::new(addr));
                             return;
                         }
                         _ => {}
                     }
                 }
+                if let (
+                    &Value::Reg(rd, ValSize::Size64),
+                    &Value::Reg(rs, ValSize::Size64),
+                    &Value::Imm(_, _, c),
+                ) = (dst, src1, src2)
+                {
+                    let v = match in_state.regs.get(&rs, &ValSize::Size64).v {
+                        // lea rd, [rs + c] with rs < 4GB can't wrap around
+                        Some(HeapValue::Bounded4GB) if rd != rs && c >= 0 && c < (1 << 32) => {
+                            Some(HeapValue::IndexPlus(rs, c))
+                        }
+                        // add rd, c may, which the carry flag tells
+                        Some(HeapValue::IndexPlus(index, offset)) if rd == rs && c >= 0 => offset
+                            .checked_add(c)
+                            .map(|sum| HeapValue::WrappingIndexPlus(index, sum)),
+                        _ => None,
+                    };
+                    if let Some(v) = v {
+                        in_state
+                            .regs
+                            .set(&rd, &ValSize::Size64, HeapValueLattice::new(v));
+                        return;
+                    }
+                }
+            }
+            // shr rd, n of a value below 4GB, e.g. a byte count turned into
+            // the element count of a rep string instruction
+            Binopcode::Shr => {
+                if let (
+                    &Value::Reg(rd, ValSize::Size64),
+                    &Value::Reg(rs, ValSize::Size64),
+                    &Value::Imm(_, _, n),
+                ) = (dst, src1, src2)
+                {
+                    let shift = match in_state.regs.get(&rs, &ValSize::Size64).v {
+                        Some(HeapValue::Bounded4GB) => n & 0x3f,
+                        Some(HeapValue::Bounded4GBShr(bits)) => bits as i64 + (n & 0x3f),
+                        _ => 0,
+                    };
+                    if shift > 0 {
+                        let v = HeapValue::Bounded4GBShr(std::cmp::min(shift, 32) as u8);
+                        in_state
+                            .regs
+                            .set(&rd, &ValSize::Size64, HeapValueLattice::new(v));
+                        return;
+                    }
+                }
             }
             _ => {}
         }
@@ -111,7 +299,7 @@ pub fn is_globalbase_access(in_state: &HeapLattice, memargs: &MemArgs) -> bool {
             assert_eq!(size.to_u32(), 64);
             let base = in_state.regs.get(regnum, size);
             if let Some(v) = base.v {
-                if let HeapValue::HeapBase = v {
+                if let HeapValue::HeapBase(_) = v {
                     return true;
                 }
             }
@@ -120,22 +308,182 @@ pub fn is_globalbase_access(in_state: &HeapLattice, memargs: &MemArgs) -> bool {
     false
 }
 
+// Successor states of a two-way branch on a flag: `refine` adds what is
+// known when the condition holds, on the taken edge if `holds_if_taken` and
+// on the fallthrough otherwise. The flag is consumed on both edges.
+fn branch_states(
+    in_state: &HeapLattice,
+    succ_addrs: &Vec<u64>,
+    holds_if_taken: bool,
+    refine: impl FnOnce(&mut HeapLattice),
+) -> Vec<(u64, HeapLattice)> {
+    let mut holds_state = in_state.clone();
+    let mut other_state = in_state.clone();
+    refine(&mut holds_state);
+    holds_state.regs.zf = Default::default();
+    other_state.regs.zf = Default::default();
+    // succ_addrs[0] is the fallthrough, succ_addrs[1] the branch target
+    if holds_if_taken {
+        vec![(succ_addrs[0], other_state), (succ_addrs[1], holds_state)]
+    } else {
+        vec![(succ_addrs[0], holds_state), (succ_addrs[1], other_state)]
+    }
+}
+
+fn clobbers_reg(stmt: &Stmt, regnum: u8) -> bool {
+    match stmt {
+        Stmt::Binop(Binopcode::Cmp, _, _, _) | Stmt::Binop(Binopcode::Test, _, _, _) => false,
+        Stmt::Clear(Value::Reg(dst, size), _)
+        | Stmt::Unop(_, Value::Reg(dst, size), _)
+        | Stmt::Binop(_, Value::Reg(dst, size), _, _) => *dst == regnum && !size.is_vector(),
+        Stmt::Call(_) => true,
+        _ => false,
+    }
+}
+
+// mem[vmctx + field_offset]
+pub fn is_vmctx_field_load(in_state: &HeapLattice, memargs: &MemArgs, field_offset: usize) -> bool {
+    let (regnum, offset) = match memargs {
+        MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => (regnum, 0),
+        MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, offset)) => {
+            (regnum, *offset)
+        }
+        _ => return false,
+    };
+    if let Some(HeapValue::VMCtxPtr) = in_state.regs.get(regnum, &ValSize::Size64).v {
+        return offset >= 0 && offset as usize == field_offset;
+    }
+    false
+}
+
 impl HeapAnalyzer {
+    /// The vmctx fields of `memory`, if the strategy has such a memory.
+    pub fn vmctx_memory(&self, memory: MemoryIdx) -> Option<VMCtxMemory> {
+        match &self.strategy {
+            HeapStrategy::HeapPtrFirstArgWithGuards => None,
+            HeapStrategy::VMCtxFirstArgWithGuards {
+                vmctx_heap_base_ptr_offset,
+            } if memory == 0 => Some(VMCtxMemory {
+                heap_base_ptr_offset: *vmctx_heap_base_ptr_offset,
+                heap_bound_offset: None,
+            }),
+            HeapStrategy::VMCtxFirstArgWithBoundsChecks {
+                vmctx_heap_base_ptr_offset,
+                vmctx_heap_bound_offset,
+            } if memory == 0 => Some(VMCtxMemory {
+                heap_base_ptr_offset: *vmctx_heap_base_ptr_offset,
+                heap_bound_offset: Some(*vmctx_heap_bound_offset),
+            }),
+            HeapStrategy::VMCtxFirstArgMultiMemory { memories } => {
+                memories.get(memory as usize).copied()
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `memory` relies on guard regions rather than explicit
+    /// bounds checks.
+    pub fn has_guards(&self, memory: MemoryIdx) -> bool {
+        match self.strategy {
+            HeapStrategy::HeapPtrFirstArgWithGuards => memory == 0,
+            _ => match self.vmctx_memory(memory) {
+                Some(layout) => layout.heap_bound_offset.is_none(),
+                None => false,
+            },
+        }
+    }
+
+    // An index that can be added to the base of `memory`
+    fn is_bounded_index(&self, memory: MemoryIdx, index: HeapValue) -> bool {
+        match index {
+            HeapValue::Bounded4GB => self.has_guards(memory),
+            HeapValue::CheckedIndex(checked_memory, _) => checked_memory == memory,
+            _ => false,
+        }
+    }
+
+    // The clear of zf that follows `add reg, c` of a WrappingIndexPlus
+    fn is_add_carry(&self, in_state: &HeapLattice, srcs: &Vec<Value>) -> bool {
+        if let [Value::Reg(regnum, ValSize::Size64), Value::Imm(..)] = srcs.as_slice() {
+            if let Some(HeapValue::WrappingIndexPlus(..)) =
+                in_state.regs.get(regnum, &ValSize::Size64).v
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    // The heap base or bound loaded by `mem[vmctx + offset]`, if any
+    fn aeval_vmctx_field(&self, in_state: &HeapLattice, memargs: &MemArgs) -> Option<HeapValue> {
+        let mut memory = 0;
+        while let Some(layout) = self.vmctx_memory(memory) {
+            if is_vmctx_field_load(in_state, memargs, layout.heap_base_ptr_offset) {
+                return Some(HeapValue::HeapBase(memory));
+            }
+            if let Some(bound_offset) = layout.heap_bound_offset {
+                if is_vmctx_field_load(in_state, memargs, bound_offset) {
+                    return Some(HeapValue::HeapBound(memory));
+                }
+            }
+            memory += 1;
+        }
+        None
+    }
+
+    // cmp idx, bound, or cmp tmp, bound with tmp = idx + c not wrapped
+    // around: lea of a 32-bit index, or for a memory64 index an add whose
+    // carry was checked. The full 64-bit index is compared.
+    fn aeval_cmp(
+        &self,
+        in_state: &HeapLattice,
+        opcode: &Binopcode,
+        src1: &Value,
+        src2: &Value,
+    ) -> HeapValueLattice {
+        if let (
+            Binopcode::Cmp,
+            Value::Reg(regnum1, ValSize::Size64),
+            Value::Reg(regnum2, ValSize::Size64),
+        ) = (opcode, src1, src2)
+        {
+            if let Some(HeapValue::HeapBound(memory)) =
+                in_state.regs.get(regnum2, &ValSize::Size64).v
+            {
+                let (index, offset) = match in_state.regs.get(regnum1, &ValSize::Size64).v {
+                    Some(HeapValue::IndexPlus(index, offset)) => (index, offset),
+                    _ => (*regnum1, 0),
+                };
+                return HeapValueLattice::new(HeapValue::BoundsCheckFlag(index, memory, offset));
+            }
+        }
+        Default::default()
+    }
+
     pub fn aeval_unop(&self, in_state: &HeapLattice, value: &Value) -> HeapValueLattice {
         match value {
             Value::Mem(memsize, memargs) => {
-                if is_globalbase_access(in_state, memargs) {
-                    return HeapValueLattice::new(HeapValue::GlobalsBase);
+                match self.strategy {
+                    HeapStrategy::HeapPtrFirstArgWithGuards => {
+                        if is_globalbase_access(in_state, memargs) {
+                            return HeapValueLattice::new(HeapValue::GlobalsBase);
+                        }
+                    }
+                    _ => {
+                        if let Some(v) = self.aeval_vmctx_field(in_state, memargs) {
+                            return HeapValueLattice::new(v);
+                        }
+                    }
                 }
                 if is_stack_access(value) {
-                    let offset = extract_stack_offset(memargs);
-                    let v = in_state.stack.get(offset, memsize.to_u32() / 8);
-                    return v;
+                    if let Some(offset) = extract_stack_offset(memargs) {
+                        return in_state.stack.get(offset, memsize.to_u32() / 8);
+                    }
                 }
             }
 
             Value::Reg(regnum, size) => {
-                if let ValSize::SizeOther = size {
+                if *size == ValSize::SizeOther || size.is_vector() {
                     return Default::default();
                 };
                 if size.to_u32() <= 32 {
@@ -146,9 +494,9 @@ impl HeapAnalyzer {
             }
 
             Value::Imm(_, _, immval) => {
-                if (*immval as u64) == self.metadata.guest_table_0 {
+                if Some(*immval as u64) == self.metadata.guest_table_0 {
                     return HeapValueLattice::new(HeapValue::GuestTable0);
-                } else if (*immval as u64) == self.metadata.lucet_tables {
+                } else if Some(*immval as u64) == self.metadata.lucet_tables {
                     return HeapValueLattice::new(HeapValue::LucetTables);
                 } else if (*immval >= 0) && (*immval < (1 << 32)) {
                     return HeapValueLattice::new(HeapValue::Bounded4GB);
@@ -1,18 +1,74 @@
 use crate::analyses::AbstractAnalyzer;
 use crate::lattices::reachingdefslattice::LocIdx;
 use crate::lattices::stackgrowthlattice::StackGrowthLattice;
-use crate::utils::ir_utils::{get_imm_offset, is_rbp, is_rsp};
-use crate::utils::lifter::{Binopcode, Stmt, Unopcode};
+use crate::utils::ir_utils::{is_partial_frame_reg, is_rbp, is_rsp};
+use crate::utils::lifter::{Binopcode, IRMap, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value};
+use crate::StackStrategy;
+use yaxpeax_x86::long_mode::Opcode;
 
-pub struct StackAnalyzer {}
+pub struct StackAnalyzer {
+    pub strategy: StackStrategy,
+    /// Address of the function's entry block, where a stack limit check
+    /// must be for `StackStrategy::VMCtxStackLimit`.
+    pub entrypoint: u64,
+}
 
 impl AbstractAnalyzer<StackGrowthLattice> for StackAnalyzer {
     fn init_state(&self) -> StackGrowthLattice {
         StackGrowthLattice::new((0, 4096, 0))
     }
 
+    fn process_branch(
+        &self,
+        irmap: &IRMap,
+        in_state: &StackGrowthLattice,
+        succ_addrs: &Vec<u64>,
+        addr: &u64,
+    ) -> Vec<(u64, StackGrowthLattice)> {
+        if let (2, Some((frame_size, safe_if_taken)), Some((stackgrowth, probestack, rbp))) = (
+            succ_addrs.len(),
+            self.stack_limit_check(irmap, addr),
+            in_state.v,
+        ) {
+            // rsp >= limit + frame_size, so the stack is valid down to the
+            // limit, and the guard page is somewhere below it.
+            let checked_probestack = std::cmp::max(probestack, frame_size - stackgrowth + 4096);
+            log::debug!(
+                "branch at 0x{:x}: stack limit check, probestack = {}",
+                addr,
+                checked_probestack
+            );
+            let checked_state = StackGrowthLattice::new((stackgrowth, checked_probestack, rbp));
+            // succ_addrs[0] is the fallthrough, succ_addrs[1] the branch target
+            if safe_if_taken {
+                vec![
+                    (succ_addrs[0], in_state.clone()),
+                    (succ_addrs[1], checked_state),
+                ]
+            } else {
+                vec![
+                    (succ_addrs[0], checked_state),
+                    (succ_addrs[1], in_state.clone()),
+                ]
+            }
+        } else {
+            succ_addrs
+                .into_iter()
+                .map(|addr| (addr.clone(), in_state.clone()))
+                .collect()
+        }
+    }
+
     fn aexec(&self, in_state: &mut StackGrowthLattice, ir_instr: &Stmt, _loc_idx: &LocIdx) -> () {
         match ir_instr {
+            Stmt::Binop(Binopcode::Cmp, _, _, _) => (),
+            Stmt::Binop(Binopcode::Test, _, _, _) => (),
+            // writing part of rsp or rbp loses track of the frame
+            Stmt::Clear(dst, _) | Stmt::Unop(_, dst, _) | Stmt::Binop(_, dst, _, _)
+                if is_partial_frame_reg(dst) =>
+            {
+                *in_state = Default::default()
+            }
             Stmt::Clear(dst, _) => {
                 if is_rsp(dst) {
                     *in_state = Default::default()
@@ -32,46 +88,58 @@ impl AbstractAnalyzer<StackGrowthLattice> for StackAnalyzer {
                     };
                 }
             }
+            // lea rbp, [rsp + c]
+            Stmt::Binop(Binopcode::Add, dst, src1, Value::Imm(_, _, offset))
+                if is_rbp(dst) && is_rsp(src1) =>
+            {
+                if let Some((stackgrowth, probestack, _)) = in_state.v {
+                    *in_state = StackGrowthLattice {
+                        v: Some((stackgrowth, probestack, stackgrowth + offset)),
+                    };
+                }
+            }
             Stmt::Unop(_, dst, _) => {
                 if is_rsp(dst) {
                     *in_state = Default::default()
                 }
             }
-            Stmt::Binop(Binopcode::Cmp, _, _, _) => (),
-            Stmt::Binop(Binopcode::Test, _, _, _) => (),
             Stmt::Binop(opcode, dst, src1, src2) => {
                 if is_rsp(dst) {
-                    if is_rsp(src1) {
-                        let offset = get_imm_offset(src2);
-                        if let Some((x, probestack, rbp)) = in_state.v {
-                            match opcode {
-                                Binopcode::Add => {
-                                    *in_state = StackGrowthLattice {
-                                        v: Some((x + offset, probestack, rbp)),
-                                    }
-                                }
-                                Binopcode::Sub => {
-                                    if (offset - x) > probestack + 4096 {
-                                        panic!("Probestack violation")
-                                    } else if (offset - x) > probestack {
-                                        //if we touch next page after the space
-                                        //we've probed, it cannot skip guard page
-                                        *in_state = StackGrowthLattice {
-                                            v: Some((x - offset, probestack + 4096, rbp)),
-                                        };
-                                        return;
-                                    }
-                                    *in_state = StackGrowthLattice {
-                                        v: Some((x - offset, probestack, rbp)),
-                                    }
+                    // Any rsp write other than adding or subtracting a
+                    // constant makes stackgrowth unknown, which the checker
+                    // reports.
+                    match (opcode, in_state.v, is_rsp(src1), src2) {
+                        (
+                            Binopcode::Add,
+                            Some((x, probestack, rbp)),
+                            true,
+                            Value::Imm(_, _, offset),
+                        ) => {
+                            *in_state = StackGrowthLattice {
+                                v: Some((x + offset, probestack, rbp)),
+                            }
+                        }
+                        (
+                            Binopcode::Sub,
+                            Some((x, probestack, rbp)),
+                            true,
+                            Value::Imm(_, _, offset),
+                        ) => {
+                            if skips_guard_page(*offset, x, probestack) {
+                                *in_state = Default::default()
+                            } else if (offset - x) > probestack {
+                                //if we touch next page after the space
+                                //we've probed, it cannot skip guard page
+                                *in_state = StackGrowthLattice {
+                                    v: Some((x - offset, probestack + 4096, rbp)),
+                                };
+                            } else {
+                                *in_state = StackGrowthLattice {
+                                    v: Some((x - offset, probestack, rbp)),
                                 }
-                                _ => panic!("Illegal RSP write"),
                             }
-                        } else {
-                            *in_state = Default::default()
                         }
-                    } else {
-                        *in_state = Default::default()
+                        _ => *in_state = Default::default(),
                     }
                 }
             }
@@ -89,3 +157,123 @@ impl AbstractAnalyzer<StackGrowthLattice> for StackAnalyzer {
         }
     }
 }
+
+/// Whether `sub rsp, offset` at stackgrowth `x` moves rsp past the guard
+/// page below the probed part of the stack.
+pub fn skips_guard_page(offset: i64, x: i64, probestack: i64) -> bool {
+    (offset - x) > probestack + 4096
+}
+
+fn writes_reg(stmt: &Stmt, regnum: u8) -> bool {
+    match stmt {
+        Stmt::Binop(Binopcode::Cmp, _, _, _) | Stmt::Binop(Binopcode::Test, _, _, _) => false,
+        Stmt::Clear(Value::Reg(dst, size), _)
+        | Stmt::Unop(_, Value::Reg(dst, size), _)
+        | Stmt::Binop(_, Value::Reg(dst, size), _, _) => *dst == regnum && !size.is_vector(),
+        Stmt::Call(_) => true,
+        _ => false,
+    }
+}
+
+// mem[rdi + offset]
+fn is_vmctx_stack_limit(v: &Value, vmctx_stack_limit_offset: usize) -> bool {
+    if let Value::Mem(
+        ValSize::Size64,
+        MemArgs::Mem2Args(MemArg::Reg(7, ValSize::Size64), MemArg::Imm(_, _, offset)),
+    ) = v
+    {
+        return *offset >= 0 && *offset as usize == vmctx_stack_limit_offset;
+    }
+    false
+}
+
+impl StackAnalyzer {
+    // Recognizes a stack limit check ending the entry block:
+    //
+    //   mov r, [rdi + vmctx_stack_limit_offset]
+    //   add r, frame_size        (optional)
+    //   cmp rsp, r               (or `cmp r, rsp`, or `cmp rsp, [rdi + ...]`)
+    //   jb/jae trap
+    //
+    // Returns the frame size, and whether the taken edge is the one on which
+    // rsp >= limit + frame_size.
+    fn stack_limit_check(&self, irmap: &IRMap, addr: &u64) -> Option<(i64, bool)> {
+        let vmctx_stack_limit_offset = match self.strategy {
+            StackStrategy::VMCtxStackLimit {
+                vmctx_stack_limit_offset,
+            } => vmctx_stack_limit_offset,
+            StackStrategy::ProbeStack => return None,
+        };
+        if *addr != self.entrypoint {
+            return None;
+        }
+        let stmts: Vec<&Stmt> = irmap
+            .get(addr)?
+            .iter()
+            .flat_map(|(_, stmts)| stmts.iter())
+            .collect();
+        let (prologue, cmp, branch) = match stmts.as_slice() {
+            [prologue @ .., cmp, branch] => (prologue, cmp, branch),
+            _ => return None,
+        };
+        let taken_if_below = match branch {
+            Stmt::Branch(Opcode::JB, _) => true,
+            Stmt::Branch(Opcode::JNB, _) => false,
+            _ => return None,
+        };
+
+        // rdi still holds vmctx, and (register, frame size) holding limit + frame size
+        let mut vmctx = true;
+        let mut limit: Option<(u8, i64)> = None;
+        for stmt in prologue {
+            match (stmt, limit) {
+                (Stmt::Unop(Unopcode::Mov, Value::Reg(regnum, ValSize::Size64), src), _)
+                    if vmctx && is_vmctx_stack_limit(src, vmctx_stack_limit_offset) =>
+                {
+                    limit = Some((*regnum, 0));
+                }
+                (
+                    Stmt::Binop(
+                        Binopcode::Add,
+                        Value::Reg(regnum, ValSize::Size64),
+                        Value::Reg(_, ValSize::Size64),
+                        Value::Imm(_, _, imm),
+                    ),
+                    Some((limit_reg, frame_size)),
+                ) if *regnum == limit_reg && *imm >= 0 => {
+                    limit = Some((limit_reg, frame_size + imm));
+                }
+                _ => {
+                    if let Some((limit_reg, _)) = limit {
+                        if writes_reg(stmt, limit_reg) {
+                            limit = None;
+                        }
+                    }
+                }
+            }
+            if writes_reg(stmt, 7) {
+                vmctx = false;
+            }
+        }
+
+        let limit_size = |v: &Value| match (v, limit) {
+            (Value::Reg(regnum, ValSize::Size64), Some((limit_reg, frame_size)))
+                if *regnum == limit_reg =>
+            {
+                Some(frame_size)
+            }
+            (v, _) if vmctx && is_vmctx_stack_limit(v, vmctx_stack_limit_offset) => Some(0),
+            _ => None,
+        };
+        let (frame_size, safe_if_below) = match cmp {
+            Stmt::Binop(Binopcode::Cmp, _, Value::Reg(4, ValSize::Size64), limit_val) => {
+                (limit_size(limit_val)?, false)
+            }
+            Stmt::Binop(Binopcode::Cmp, _, limit_val, Value::Reg(4, ValSize::Size64)) => {
+                (limit_size(limit_val)?, true)
+            }
+            _ => return None,
+        };
+        Some((frame_size, taken_if_below == safe_if_below))
+    }
+}
@@ -1,136 +1,286 @@
-use veriwasm::{analyses, checkers, utils};
+use veriwasm::profile::RuntimeProfile;
+use veriwasm::sarif::to_sarif;
+use veriwasm::verify::{
+    check_functions, load_module, module_report, panic_message, DotOptions, DumpState,
+    LoadedModule, Verdict, VerifyConfig,
+};
+use veriwasm::{analyses, report, utils};
 
 use analyses::call_analyzer::CallAnalyzer;
 use analyses::heap_analyzer::HeapAnalyzer;
 use analyses::reaching_defs::{analyze_reaching_defs, ReachingDefnAnalyzer};
 use analyses::run_worklist;
 use analyses::stack_analyzer::StackAnalyzer;
-use checkers::call_checker::check_calls;
-use checkers::heap_checker::check_heap;
-use checkers::stack_checker::check_stack;
-use utils::ir_utils::has_indirect_calls;
-use utils::utils::{fully_resolved_cfg, get_data};
+use report::Timings;
+use utils::explain::explain;
+use utils::utils::partially_resolved_cfg;
 
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 use serde_json;
+use std::collections::BTreeMap;
 use std::fs;
 use std::panic;
-use std::time::Instant;
-use utils::utils::{load_metadata, load_program};
-use yaxpeax_core::analyses::control_flow::check_cfg_integrity;
+use std::path::Path;
+
+// Exit codes, so that CI can tell a real SFI violation apart from a verifier
+// crash. If a module has both unsafe functions and internal errors, the unsafe
+// verdict wins.
+const EXIT_UNSAFE: i32 = 1;
+const EXIT_UNSUPPORTED_INPUT: i32 = 2;
+const EXIT_INTERNAL_ERROR: i32 = 3;
 
 pub struct Config {
     module_path: String,
-    _num_jobs: u32,
     output_path: String,
     has_output: bool,
+    output_format: OutputFormat,
     _quiet: bool,
-    only_func: Option<String>,
+    dump: Option<DumpOptions>,
+    verify: VerifyConfig,
+}
+
+pub struct DumpOptions {
+    dir: String,
+    state: Option<DumpState>,
 }
 
-fn run(config: Config) {
-    let mut func_counter = 0;
-    let mut info: Vec<(std::string::String, usize, f64, f64, f64, f64)> = vec![];
-    let program = load_program(&config.module_path);
-    println!("Loading Metadata");
-    let metadata = load_metadata(&config.module_path);
-    let (x86_64_data, func_addrs, plt) = get_data(&config.module_path, &program);
-    let valid_funcs: Vec<u64> = func_addrs.clone().iter().map(|x| x.0).collect();
-    for (addr, func_name) in func_addrs {
-        if config.only_func.is_some() && func_name != config.only_func.as_ref().unwrap().as_str() {
-            continue;
+pub enum OutputFormat {
+    Json,
+    Sarif,
+}
+
+// Symbol names can contain characters that aren't safe in file names.
+fn dot_file_stem(func_name: &str) -> String {
+    func_name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '.' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Loads the module at `module_path`, reporting any failure, including a
+/// panic in yaxpeax, as a message.
+fn load_module_caught(
+    module_path: &str,
+    profile: &RuntimeProfile,
+) -> Result<(Vec<u8>, LoadedModule), String> {
+    let data = fs::read(module_path).map_err(|err| err.to_string())?;
+    match panic::catch_unwind(|| load_module(module_path, &data, profile)) {
+        Ok(Ok(module)) => Ok((data, module)),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(payload) => Err(panic_message(&payload)),
+    }
+}
+
+fn run(config: Config) -> i32 {
+    // Panics are caught per function below and reported in the summary, so
+    // only log them here rather than printing a backtrace hint for each one.
+    panic::set_hook(Box::new(|info| log::debug!("{}", info)));
+
+    let (data, module) = match load_module_caught(&config.module_path, &config.verify.profile) {
+        Ok(loaded) => loaded,
+        Err(msg) => {
+            println!("Unsupported input: {}", msg);
+            return EXIT_UNSUPPORTED_INPUT;
         }
-        println!("Generating CFG for {:?}", func_name);
-        let start = Instant::now();
-        let (cfg, irmap) = fully_resolved_cfg(&program, &x86_64_data.contexts, &metadata, addr);
-        func_counter += 1;
-        println!("Analyzing: {:?}", func_name);
-        //let irmap = lift_cfg(&program, &cfg, &metadata);
-        check_cfg_integrity(&cfg.blocks, &cfg.graph);
-
-        let stack_start = Instant::now();
-        let stack_analyzer = StackAnalyzer {};
-        let stack_result = run_worklist(&cfg, &irmap, &stack_analyzer);
-        let stack_safe = check_stack(stack_result, &irmap, &stack_analyzer);
-        if !stack_safe {
-            panic!("Not Stack Safe");
+    };
+
+    if let Some(dump) = &config.dump {
+        if let Err(err) = fs::create_dir_all(&dump.dir) {
+            println!("Unable to create {}: {}", dump.dir, err);
+            return EXIT_INTERNAL_ERROR;
+        }
+    }
+
+    let dot = config
+        .dump
+        .as_ref()
+        .map(|dump| DotOptions { state: dump.state });
+    let results = match check_functions(&module, &config.verify, dot) {
+        Ok(results) => results,
+        Err(err) => {
+            println!("Unsupported input: {}", err);
+            return EXIT_UNSUPPORTED_INPUT;
         }
+    };
+    if let Some(dump) = &config.dump {
+        for result in &results {
+            if let Some(dot) = &result.dot {
+                let path =
+                    Path::new(&dump.dir).join(format!("{}.dot", dot_file_stem(&result.func_name)));
+                if let Err(err) = fs::write(&path, dot) {
+                    println!("Unable to write {}: {}", path.display(), err);
+                }
+            }
+        }
+    }
 
-        println!("Checking Heap Safety");
-        let heap_start = Instant::now();
-        let heap_analyzer = HeapAnalyzer {
-            metadata: metadata.clone(),
+    let mut timings = Timings::default();
+    for result in &results {
+        timings.add(&result.timings);
+    }
+    println!("Verified {:?} functions", results.len());
+    println!(
+        "Total time = {:?}s CFG: {:?} Stack: {:?}s Heap: {:?}s Call: {:?}s",
+        timings.total_secs(),
+        timings.cfg_secs,
+        timings.stack_secs,
+        timings.heap_secs,
+        timings.call_secs
+    );
+    if config.has_output {
+        let mut report = module_report(&data, &config.verify, &results);
+        report.module.path = config.module_path.clone();
+        let data = match config.output_format {
+            OutputFormat::Json => serde_json::to_string_pretty(&report).unwrap(),
+            OutputFormat::Sarif => serde_json::to_string_pretty(&to_sarif(&report)).unwrap(),
         };
-        let heap_result = run_worklist(&cfg, &irmap, &heap_analyzer);
-        let heap_safe = check_heap(heap_result, &irmap, &heap_analyzer);
-        if !heap_safe {
-            panic!("Not Heap Safe");
+        println!("Dumping report to {}", config.output_path);
+        if let Err(err) = fs::write(&config.output_path, data) {
+            println!("Unable to write {}: {}", config.output_path, err);
+            return EXIT_INTERNAL_ERROR;
+        }
+    }
+
+    let mut num_unsafe = 0;
+    let mut num_errors = 0;
+    println!("Summary:");
+    for result in &results {
+        match &result.verdict {
+            Verdict::Safe => println!("  PASS  {}", result.func_name),
+            Verdict::Unsafe(violations) => {
+                num_unsafe += 1;
+                println!("  FAIL  {}", result.func_name);
+                for violation in violations {
+                    println!("        {}", violation);
+                }
+            }
+            Verdict::InternalError(msg) => {
+                num_errors += 1;
+                println!("  ERROR {}: {}", result.func_name, msg);
+            }
+        }
+    }
+    println!(
+        "{} passed, {} unsafe, {} internal errors",
+        results.len() - num_unsafe - num_errors,
+        num_unsafe,
+        num_errors
+    );
+
+    let mut unknown_opcodes: BTreeMap<&str, usize> = BTreeMap::new();
+    for result in &results {
+        for opcode in &result.unknown_opcodes {
+            *unknown_opcodes.entry(opcode).or_insert(0) += 1;
+        }
+    }
+    if !unknown_opcodes.is_empty() {
+        println!("Unknown opcodes (lifted conservatively):");
+        for (opcode, count) in &unknown_opcodes {
+            println!("  {:<12} {}", opcode, count);
         }
+    }
+    println!("Done!");
 
-        let call_start = Instant::now();
-        println!("Checking Call Safety");
-        if has_indirect_calls(&irmap) {
-            let reaching_defs = analyze_reaching_defs(&cfg, &irmap, metadata.clone());
+    if num_unsafe > 0 {
+        EXIT_UNSAFE
+    } else if num_errors > 0 {
+        EXIT_INTERNAL_ERROR
+    } else {
+        0
+    }
+}
+
+/// Prints the annotated listing of one function for `veriwasm explain`.
+fn explain_function(
+    module_path: &str,
+    func_name: &str,
+    state: DumpState,
+    profile: &RuntimeProfile,
+) -> i32 {
+    let module = match load_module_caught(module_path, profile) {
+        Ok((_, module)) => module,
+        Err(msg) => {
+            println!("Unsupported input: {}", msg);
+            return EXIT_UNSUPPORTED_INPUT;
+        }
+    };
+    let addr = match module.func_addrs.iter().find(|(_, name)| name == func_name) {
+        Some((addr, _)) => *addr,
+        None => {
+            println!("No function named {:?} in {}", func_name, module_path);
+            return EXIT_UNSUPPORTED_INPUT;
+        }
+    };
+    let valid_funcs = module.valid_funcs();
+    let (cfg, irmap, _) = partially_resolved_cfg(
+        &module.program,
+        &module.x86_64_data.contexts,
+        &module.metadata,
+        addr,
+    );
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let written = match state {
+        DumpState::Stack => {
+            let stack_analyzer = StackAnalyzer {
+                strategy: module.profile.stack,
+                entrypoint: cfg.entrypoint,
+            };
+            let stack_result = run_worklist(&cfg, &irmap, &stack_analyzer);
+            explain(
+                &module.program,
+                &irmap,
+                &stack_analyzer,
+                &stack_result,
+                &mut out,
+            )
+        }
+        DumpState::Heap => {
+            let heap_analyzer = HeapAnalyzer {
+                metadata: module.metadata.clone(),
+                strategy: module.profile.heap.clone(),
+            };
+            let heap_result = run_worklist(&cfg, &irmap, &heap_analyzer);
+            explain(
+                &module.program,
+                &irmap,
+                &heap_analyzer,
+                &heap_result,
+                &mut out,
+            )
+        }
+        DumpState::Call => {
+            let reaching_defs = analyze_reaching_defs(&cfg, &irmap, module.metadata.clone());
             let call_analyzer = CallAnalyzer {
-                metadata: metadata.clone(),
-                reaching_defs: reaching_defs.clone(),
+                metadata: module.metadata.clone(),
+                reaching_defs: reaching_defs,
                 reaching_analyzer: ReachingDefnAnalyzer {
                     cfg: cfg.clone(),
                     irmap: irmap.clone(),
                 },
-                funcs: valid_funcs.clone(),
+                funcs: valid_funcs,
             };
             let call_result = run_worklist(&cfg, &irmap, &call_analyzer);
-            let call_safe = check_calls(call_result, &irmap, &call_analyzer, &valid_funcs, &plt);
-            if !call_safe {
-                panic!("Not Call Safe");
-            }
+            explain(
+                &module.program,
+                &irmap,
+                &call_analyzer,
+                &call_result,
+                &mut out,
+            )
+        }
+    };
+    match written {
+        Ok(()) => 0,
+        Err(err) => {
+            println!("Unable to write listing: {}", err);
+            EXIT_INTERNAL_ERROR
         }
-        let end = Instant::now();
-        info.push((
-            func_name.to_string(),
-            cfg.blocks.len(),
-            (stack_start - start).as_secs_f64(),
-            (heap_start - stack_start).as_secs_f64(),
-            (call_start - heap_start).as_secs_f64(),
-            (end - call_start).as_secs_f64(),
-        ));
-        println!(
-            "Verified {:?} at {:?} blocks. CFG: {:?}s Stack: {:?}s Heap: {:?}s Calls: {:?}s",
-            func_name,
-            cfg.blocks.len(),
-            (stack_start - start).as_secs_f64(),
-            (heap_start - stack_start).as_secs_f64(),
-            (call_start - heap_start).as_secs_f64(),
-            (end - call_start).as_secs_f64()
-        );
-    }
-    if config.has_output {
-        let data = serde_json::to_string(&info).unwrap();
-        println!("Dumping Stats to {}", config.output_path);
-        fs::write(config.output_path, data).expect("Unable to write file");
-    }
-
-    let mut total_cfg_time = 0.0;
-    let mut total_stack_time = 0.0;
-    let mut total_heap_time = 0.0;
-    let mut total_call_time = 0.0;
-    for (_, _, cfg_time, stack_time, heap_time, call_time) in &info {
-        total_cfg_time += cfg_time;
-        total_stack_time += stack_time;
-        total_heap_time += heap_time;
-        total_call_time += call_time;
     }
-    println!("Verified {:?} functions", func_counter);
-    println!(
-        "Total time = {:?}s CFG: {:?} Stack: {:?}s Heap: {:?}s Call: {:?}s",
-        total_cfg_time + total_stack_time + total_heap_time + total_call_time,
-        total_cfg_time,
-        total_stack_time,
-        total_heap_time,
-        total_call_time
-    );
-    println!("Done!");
 }
 
 fn main() {
@@ -157,7 +307,7 @@ fn main() {
                 .short("o")
                 .long("output")
                 .takes_value(true)
-                .help("Path to output stats file"),
+                .help("Path to write a JSON verification report to"),
         )
         .arg(
             Arg::with_name("one function")
@@ -166,10 +316,76 @@ fn main() {
                 .takes_value(true)
                 .help("Single function to process (rather than whole module"),
         )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["json", "sarif"])
+                .requires("stats output path")
+                .help("Format of the report written with -o (default json)"),
+        )
+        .arg(
+            Arg::with_name("dump cfg")
+                .long("dump-cfg")
+                .takes_value(true)
+                .help("Directory to write one Graphviz (DOT) CFG per function to"),
+        )
+        .arg(
+            Arg::with_name("dump state")
+                .long("dump-state")
+                .takes_value(true)
+                .possible_values(&["heap", "stack", "call"])
+                .requires("dump cfg")
+                .help("Abstract state to label --dump-cfg blocks with at block entry"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .help("Runtime profile (JSON) describing the module's ABI (default Lucet)"),
+        )
         .arg(Arg::with_name("quiet").short("q").long("quiet"))
+        .subcommand(
+            SubCommand::with_name("explain")
+                .about("Prints each instruction of a function with its IR and abstract state")
+                .arg(
+                    Arg::with_name("one function")
+                        .short("f")
+                        .long("func")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Function to explain"),
+                )
+                .arg(
+                    Arg::with_name("analysis")
+                        .long("analysis")
+                        .takes_value(true)
+                        .possible_values(&["heap", "stack", "call"])
+                        .help("Analysis whose state to show (default heap)"),
+                ),
+        )
         .get_matches();
 
     let module_path = matches.value_of("module path").unwrap();
+    let profile = match matches.value_of("profile") {
+        Some(path) => match RuntimeProfile::load(path) {
+            Ok(profile) => profile,
+            Err(err) => {
+                println!("Unable to load profile {}", err);
+                std::process::exit(EXIT_UNSUPPORTED_INPUT);
+            }
+        },
+        None => RuntimeProfile::lucet(),
+    };
+    if let Some(explain_matches) = matches.subcommand_matches("explain") {
+        let func_name = explain_matches.value_of("one function").unwrap();
+        let state = match explain_matches.value_of("analysis") {
+            Some("stack") => DumpState::Stack,
+            Some("call") => DumpState::Call,
+            _ => DumpState::Heap,
+        };
+        std::process::exit(explain_function(module_path, func_name, state, &profile));
+    }
     let num_jobs_opt = matches.value_of("jobs");
     let output_path = matches.value_of("stats output path").unwrap_or("");
     let num_jobs = num_jobs_opt
@@ -179,15 +395,33 @@ fn main() {
     let only_func = matches.value_of("one function").map(|s| s.to_owned());
 
     let has_output = if output_path == "" { false } else { true };
+    let dump = matches.value_of("dump cfg").map(|dir| DumpOptions {
+        dir: dir.to_string(),
+        state: match matches.value_of("dump state") {
+            Some("heap") => Some(DumpState::Heap),
+            Some("stack") => Some(DumpState::Stack),
+            Some("call") => Some(DumpState::Call),
+            _ => None,
+        },
+    });
+    let output_format = match matches.value_of("format") {
+        Some("sarif") => OutputFormat::Sarif,
+        _ => OutputFormat::Json,
+    };
 
     let config = Config {
         module_path: module_path.to_string(),
-        _num_jobs: num_jobs,
         output_path: output_path.to_string(),
         has_output: has_output,
+        output_format,
         _quiet: quiet,
-        only_func,
+        dump,
+        verify: VerifyConfig {
+            profile,
+            num_jobs,
+            only_func,
+        },
     };
 
-    run(config);
+    std::process::exit(run(config));
 }
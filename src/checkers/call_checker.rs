@@ -1,6 +1,6 @@
 use crate::analyses::call_analyzer::CallAnalyzer;
 use crate::analyses::{AbstractAnalyzer, AnalysisResult};
-use crate::checkers::Checker;
+use crate::checkers::{Checker, Violation, ViolationKind};
 use crate::lattices::calllattice::{CallCheckLattice, CallCheckValue};
 use crate::lattices::davlattice::DAV;
 use crate::lattices::reachingdefslattice::LocIdx;
@@ -11,6 +11,7 @@ pub struct CallChecker<'a> {
     analyzer: &'a CallAnalyzer,
     funcs: &'a Vec<u64>,
     plt: &'a (u64, u64),
+    func: &'a str,
     // x86_64_data: &x86_64Data,
 }
 
@@ -20,50 +21,68 @@ pub fn check_calls(
     analyzer: &CallAnalyzer,
     funcs: &Vec<u64>,
     plt: &(u64, u64),
+    func: &str,
     // x86_64_data: &x86_64Data,
-) -> bool {
+) -> Vec<Violation> {
     CallChecker {
         irmap,
         analyzer,
         funcs,
-        plt, // x86_64_data,
+        plt,
+        func, // x86_64_data,
     }
     .check(result)
 }
 
 impl Checker<CallCheckLattice> for CallChecker<'_> {
-    fn check(&self, result: AnalysisResult<CallCheckLattice>) -> bool {
+    fn check(&self, result: AnalysisResult<CallCheckLattice>) -> Vec<Violation> {
         self.check_state_at_statements(result)
     }
 
     fn irmap(&self) -> &IRMap {
         self.irmap
     }
+    fn func_name(&self) -> &str {
+        self.func
+    }
     fn aexec(&self, state: &mut CallCheckLattice, ir_stmt: &Stmt, loc: &LocIdx) {
         self.analyzer.aexec(state, ir_stmt, loc)
     }
 
-    fn check_statement(&self, state: &CallCheckLattice, ir_stmt: &Stmt, loc_idx: &LocIdx) -> bool {
+    fn check_statement(
+        &self,
+        state: &CallCheckLattice,
+        ir_stmt: &Stmt,
+        loc_idx: &LocIdx,
+    ) -> Vec<(ViolationKind, Vec<String>)> {
+        let mut violations = vec![];
         //1. Check that all indirect calls use resolved function pointer
         if let Stmt::Call(v) = ir_stmt {
             if !self.check_indirect_call(state, v, loc_idx) {
-                println!("0x{:x} Failure Case: Indirect Call {:?}", loc_idx.addr, v);
-                return false;
+                log::debug!("0x{:x} Failure Case: Indirect Call {:?}", loc_idx.addr, v);
+                violations.push((
+                    ViolationKind::UnresolvedIndirectCall,
+                    call_target_operands(state, v),
+                ));
             }
         }
 
         // 2. Check that lookup is using resolved DAV
         if let Stmt::Unop(_, _, Value::Mem(_, memargs)) = ir_stmt {
             if !self.check_calltable_lookup(state, memargs) {
-                println!(
+                log::debug!(
                     "0x{:x} Failure Case: Lookup Call: {:?}",
-                    loc_idx.addr, memargs
+                    loc_idx.addr,
+                    memargs
                 );
                 print_mem_access(state, memargs);
-                return false;
+                violations.push((
+                    ViolationKind::UncheckedCallTableLookup,
+                    mem_access_operands(state, memargs),
+                ));
             }
         }
-        true
+        violations
     }
 }
 
@@ -102,8 +121,8 @@ impl CallChecker<'_> {
             MemArgs::Mem3Args(
                 MemArg::Reg(regnum1, ValSize::Size64),
                 MemArg::Reg(regnum2, ValSize::Size64),
-                MemArg::Imm(_, _, 8),
-            ) => match (
+                MemArg::Imm(_, _, offset),
+            ) if *offset == self.analyzer.metadata.tables.fn_ptr_offset => match (
                 state.regs.get(regnum1, &ValSize::Size64).v,
                 state.regs.get(regnum2, &ValSize::Size64).v,
             ) {
@@ -119,6 +138,21 @@ impl CallChecker<'_> {
                 | (Some(CallCheckValue::GuestTableBase), _x) => return false,
                 (_x, _y) => return true, // not a calltable lookup
             },
+            MemArgs::MemScaleDisp(
+                MemArg::Reg(regnum1, ValSize::Size64),
+                MemArg::Reg(regnum2, ValSize::Size64),
+                MemArg::Imm(_, _, scale),
+                MemArg::Imm(_, _, offset),
+            ) => match state.regs.get(regnum1, &ValSize::Size64).v {
+                Some(CallCheckValue::GuestTableBase) => {
+                    let tables = &self.analyzer.metadata.tables;
+                    *scale == 1 << tables.entry_shift
+                        && *offset == tables.fn_ptr_offset
+                        && state.regs.get(regnum2, &ValSize::Size64).v
+                            == Some(CallCheckValue::CheckedVal)
+                }
+                _ => true, // not a calltable lookup
+            },
             _ => return true, //not a calltable lookup?
         }
     }
@@ -131,6 +165,36 @@ pub fn memarg_repr(state: &CallCheckLattice, memarg: &MemArg) -> String {
     }
 }
 
+fn call_target_operands(state: &CallCheckLattice, target: &Value) -> Vec<String> {
+    match target {
+        Value::Reg(regnum, size) => vec![format!(
+            "r{:?}: {:?}",
+            regnum,
+            state.regs.get(regnum, size).v
+        )],
+        Value::Mem(_, memargs) => mem_access_operands(state, memargs),
+        _ => vec![format!("{:?}", target)],
+    }
+}
+
+pub fn mem_access_operands(state: &CallCheckLattice, memargs: &MemArgs) -> Vec<String> {
+    match memargs {
+        MemArgs::Mem1Arg(x) => vec![memarg_repr(state, x)],
+        MemArgs::Mem2Args(x, y) => vec![memarg_repr(state, x), memarg_repr(state, y)],
+        MemArgs::Mem3Args(x, y, z) | MemArgs::MemScale(x, y, z) => vec![
+            memarg_repr(state, x),
+            memarg_repr(state, y),
+            memarg_repr(state, z),
+        ],
+        MemArgs::MemScaleDisp(w, x, y, z) => vec![
+            memarg_repr(state, w),
+            memarg_repr(state, x),
+            memarg_repr(state, y),
+            memarg_repr(state, z),
+        ],
+    }
+}
+
 pub fn print_mem_access(state: &CallCheckLattice, memargs: &MemArgs) {
     match memargs {
         MemArgs::Mem1Arg(x) => log::debug!("mem[{:?}]", memarg_repr(state, x)),
@@ -151,5 +215,50 @@ pub fn print_mem_access(state: &CallCheckLattice, memargs: &MemArgs) {
             memarg_repr(state, y),
             memarg_repr(state, z)
         ),
+        MemArgs::MemScaleDisp(w, x, y, z) => log::debug!(
+            "mem[{:?} + {:?} * {:?} + {:?}]",
+            memarg_repr(state, w),
+            memarg_repr(state, x),
+            memarg_repr(state, y),
+            memarg_repr(state, z)
+        ),
     }
 }
+
+#[test]
+fn call_lea_test() {
+    use crate::analyses::reaching_defs::{analyze_reaching_defs, ReachingDefnAnalyzer};
+    use crate::analyses::run_worklist;
+    use crate::utils::ir_text::parse_ir;
+    use crate::utils::utils::LucetMetadata;
+
+    // lea rcx, [guest_table_0 + 16]; call rcx
+    let (cfg, irmap) = parse_ir(
+        "\
+block 0x0
+  0x0: r0:64 <- mov 4096:i64
+  0x7: r1:64 <- add r0:64, 16:i32
+  0xb: call r1:64
+  0xd: ret
+",
+    )
+    .unwrap();
+    let metadata = LucetMetadata {
+        guest_table_0: Some(4096),
+        ..Default::default()
+    };
+    let analyzer = CallAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: analyze_reaching_defs(&cfg, &irmap, metadata),
+        reaching_analyzer: ReachingDefnAnalyzer {
+            cfg: cfg.clone(),
+            irmap: irmap.clone(),
+        },
+        funcs: vec![],
+    };
+    let result = run_worklist(&cfg, &irmap, &analyzer);
+    let violations = check_calls(result, &irmap, &analyzer, &vec![], &(0, 0), "f");
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].kind, ViolationKind::UnresolvedIndirectCall);
+    assert_eq!(violations[0].addr, 0xb);
+}
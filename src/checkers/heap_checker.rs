@@ -1,28 +1,42 @@
 use crate::analyses::heap_analyzer::HeapAnalyzer;
 use crate::analyses::{AbstractAnalyzer, AnalysisResult};
-use crate::checkers::Checker;
+use crate::checkers::{Checker, Violation, ViolationKind};
 use crate::lattices::heaplattice::{HeapLattice, HeapValue};
 use crate::lattices::reachingdefslattice::LocIdx;
 use crate::utils::ir_utils::{is_mem_access, is_stack_access};
 use crate::utils::lifter::{IRMap, MemArg, MemArgs, Stmt, ValSize, Value};
+use crate::HeapStrategy;
 
 pub struct HeapChecker<'a> {
     irmap: &'a IRMap,
     analyzer: &'a HeapAnalyzer,
+    func: &'a str,
 }
 
 pub fn check_heap(
     result: AnalysisResult<HeapLattice>,
     irmap: &IRMap,
     analyzer: &HeapAnalyzer,
-) -> bool {
+    func: &str,
+) -> Vec<Violation> {
     HeapChecker {
         irmap: irmap,
         analyzer: analyzer,
+        func: func,
     }
     .check(result)
 }
 
+// Each heap with guard regions is a 4GB heap followed by a 4GB guard region.
+const GUARDED_HEAP_RESERVATION: i64 = 0x2_0000_0000;
+const MAX_BOUNDED_4GB: i64 = 0xffff_ffff;
+
+// Whether an access of `width` bytes at heapbase + a Bounded4GB index +
+// `offset` ends inside the guard region.
+fn in_guard_region(offset: i64, width: i64) -> bool {
+    offset >= -0x1000 && MAX_BOUNDED_4GB + offset + width <= GUARDED_HEAP_RESERVATION
+}
+
 fn memarg_is_frame(memarg: &MemArg) -> bool {
     if let MemArg::Reg(5, size) = memarg {
         assert_eq!(*size, ValSize::Size64);
@@ -51,66 +65,85 @@ fn is_frame_access(v: &Value) -> bool {
 }
 
 impl Checker<HeapLattice> for HeapChecker<'_> {
-    fn check(&self, result: AnalysisResult<HeapLattice>) -> bool {
+    fn check(&self, result: AnalysisResult<HeapLattice>) -> Vec<Violation> {
         self.check_state_at_statements(result)
     }
 
     fn irmap(&self) -> &IRMap {
         self.irmap
     }
+    fn func_name(&self) -> &str {
+        self.func
+    }
     fn aexec(&self, state: &mut HeapLattice, ir_stmt: &Stmt, loc: &LocIdx) {
         self.analyzer.aexec(state, ir_stmt, loc)
     }
 
-    fn check_statement(&self, state: &HeapLattice, ir_stmt: &Stmt, _loc_idx: &LocIdx) -> bool {
+    fn check_statement(
+        &self,
+        state: &HeapLattice,
+        ir_stmt: &Stmt,
+        _loc_idx: &LocIdx,
+    ) -> Vec<(ViolationKind, Vec<String>)> {
+        let mut violations = vec![];
         match ir_stmt {
-            //1. Check that at each call rdi = HeapBase
-            Stmt::Call(_) => match state.regs.rdi.v {
-                Some(HeapValue::HeapBase) => (),
+            //1. Check that at each call rdi = HeapBase (or VMCtxPtr, for Wasmtime)
+            Stmt::Call(_) => match (&self.analyzer.strategy, state.regs.rdi.v) {
+                (HeapStrategy::HeapPtrFirstArgWithGuards, Some(HeapValue::HeapBase(0))) => (),
+                (HeapStrategy::HeapPtrFirstArgWithGuards, _) => {
+                    violations.push((
+                        ViolationKind::HeapBaseNotPassedToCall,
+                        vec![format!("rdi: {:?}", state.regs.rdi.v)],
+                    ));
+                }
+                (_, Some(HeapValue::VMCtxPtr)) => (),
                 _ => {
                     log::debug!("Call failure {:?}", state.stack.get(0, 8));
-                    return false;
+                    violations.push((
+                        ViolationKind::HeapBaseNotPassedToCall,
+                        vec![format!("rdi: {:?}", state.regs.rdi.v)],
+                    ));
                 }
             },
             //2. Check that all load and store are safe
             Stmt::Unop(_, dst, src) => {
-                if is_mem_access(dst) && !self.check_mem_access(state, dst) {
-                    return false;
-                }
-                //stack read: probestack <= stackgrowth + c < 8K
-                if is_mem_access(src) && !self.check_mem_access(state, src) {
-                    return false;
-                }
+                self.check_mem_accesses(state, &[dst], true, &mut violations);
+                self.check_mem_accesses(state, &[src], false, &mut violations);
             }
-
             Stmt::Binop(_, dst, src1, src2) => {
-                if is_mem_access(dst) && !self.check_mem_access(state, dst) {
-                    return false;
-                }
-                if is_mem_access(src1) && !self.check_mem_access(state, src1) {
-                    return false;
-                }
-                if is_mem_access(src2) && !self.check_mem_access(state, src2) {
-                    return false;
-                }
+                self.check_mem_accesses(state, &[dst], true, &mut violations);
+                self.check_mem_accesses(state, &[src1, src2], false, &mut violations);
             }
             Stmt::Clear(dst, srcs) => {
-                if is_mem_access(dst) && !self.check_mem_access(state, dst) {
-                    return false;
-                }
+                self.check_mem_accesses(state, &[dst], true, &mut violations);
                 for src in srcs {
-                    if is_mem_access(src) && !self.check_mem_access(state, src) {
-                        return false;
-                    }
+                    self.check_mem_accesses(state, &[src], false, &mut violations);
                 }
             }
             _ => (),
         }
-        true
+        violations
     }
 }
 
 impl HeapChecker<'_> {
+    fn check_mem_accesses(
+        &self,
+        state: &HeapLattice,
+        accesses: &[&Value],
+        is_write: bool,
+        violations: &mut Vec<(ViolationKind, Vec<String>)>,
+    ) {
+        for access in accesses {
+            if is_mem_access(access) && !self.check_mem_access(state, access, is_write) {
+                violations.push((
+                    ViolationKind::UncheckedMemoryAccess,
+                    mem_access_operands(state, access),
+                ));
+            }
+        }
+    }
+
     fn check_global_access(&self, state: &HeapLattice, access: &Value) -> bool {
         if let Value::Mem(_, memargs) = access {
             match memargs {
@@ -126,7 +159,7 @@ impl HeapChecker<'_> {
                 ) => {
                     if let Some(HeapValue::GlobalsBase) = state.regs.get(regnum, &ValSize::Size64).v
                     {
-                        return *globals_offset <= 4096;
+                        return *globals_offset <= self.analyzer.metadata.globals.max_offset;
                     }
                 }
                 _ => return false,
@@ -135,6 +168,31 @@ impl HeapChecker<'_> {
         false
     }
 
+    fn check_vmctx_access(&self, state: &HeapLattice, access: &Value, is_write: bool) -> bool {
+        // Fields of the VM context (the heap base pointer, globals, table
+        // descriptors, ...) are always addressed at a constant offset from the
+        // vmctx pointer. They are only read: the analyses trust the heap base
+        // and bound loaded from it, so a store could move the heap.
+        if is_write {
+            return false;
+        }
+        if let Value::Mem(size, memargs) = access {
+            let width = (size.to_u32() / 8) as i64;
+            let (regnum, offset) = match memargs {
+                MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => (regnum, 0),
+                MemArgs::Mem2Args(
+                    MemArg::Reg(regnum, ValSize::Size64),
+                    MemArg::Imm(_, _, vmctx_offset),
+                ) => (regnum, *vmctx_offset),
+                _ => return false,
+            };
+            if let Some(HeapValue::VMCtxPtr) = state.regs.get(regnum, &ValSize::Size64).v {
+                return offset >= 0 && offset + width <= self.analyzer.metadata.vmctx.size;
+            }
+        }
+        false
+    }
+
     fn check_ripconst_access(&self, state: &HeapLattice, access: &Value) -> bool {
         if let Value::Mem(_, memargs) = access {
             match memargs {
@@ -156,7 +214,8 @@ impl HeapChecker<'_> {
                 MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64))
                 | MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), _)
                 | MemArgs::Mem3Args(MemArg::Reg(regnum, ValSize::Size64), _, _)
-                | MemArgs::MemScale(MemArg::Reg(regnum, ValSize::Size64), _, _) => {
+                | MemArgs::MemScale(MemArg::Reg(regnum, ValSize::Size64), _, _)
+                | MemArgs::MemScaleDisp(MemArg::Reg(regnum, ValSize::Size64), _, _, _) => {
                     if let Some(HeapValue::RIPConst) = state.regs.get(regnum, &ValSize::Size64).v {
                         return true;
                     }
@@ -167,52 +226,142 @@ impl HeapChecker<'_> {
         false
     }
 
+    // Without guard regions, only an access at heapbase + an index that was
+    // checked against the heap bound is in bounds, and only if the check
+    // covers every byte it accesses.
+    fn check_bounds_checked_heap_access(&self, state: &HeapLattice, access: &Value) -> bool {
+        if let Value::Mem(size, memargs) = access {
+            let width = (size.to_u32() / 8) as i64;
+            match memargs {
+                MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => {
+                    if let Some(HeapValue::CheckedAddr(_, bytes)) =
+                        state.regs.get(regnum, &ValSize::Size64).v
+                    {
+                        return width <= bytes;
+                    }
+                }
+                MemArgs::Mem2Args(
+                    MemArg::Reg(regnum1, ValSize::Size64),
+                    MemArg::Reg(regnum2, ValSize::Size64),
+                ) => {
+                    match (
+                        state.regs.get(regnum1, &ValSize::Size64).v,
+                        state.regs.get(regnum2, &ValSize::Size64).v,
+                    ) {
+                        (
+                            Some(HeapValue::HeapBase(memory)),
+                            Some(HeapValue::CheckedIndex(checked, bytes)),
+                        )
+                        | (
+                            Some(HeapValue::CheckedIndex(checked, bytes)),
+                            Some(HeapValue::HeapBase(memory)),
+                        ) if memory == checked => return width <= bytes,
+                        _ => (),
+                    }
+                }
+                MemArgs::Mem2Args(
+                    MemArg::Reg(regnum, ValSize::Size64),
+                    MemArg::Imm(_, _, offset),
+                ) => {
+                    if let Some(HeapValue::CheckedAddr(_, bytes)) =
+                        state.regs.get(regnum, &ValSize::Size64).v
+                    {
+                        return *offset >= 0 && offset + width <= bytes;
+                    }
+                }
+                MemArgs::Mem3Args(
+                    MemArg::Reg(regnum1, ValSize::Size64),
+                    MemArg::Reg(regnum2, ValSize::Size64),
+                    MemArg::Imm(_, _, offset),
+                ) => {
+                    match (
+                        state.regs.get(regnum1, &ValSize::Size64).v,
+                        state.regs.get(regnum2, &ValSize::Size64).v,
+                    ) {
+                        (
+                            Some(HeapValue::HeapBase(memory)),
+                            Some(HeapValue::CheckedIndex(checked, bytes)),
+                        )
+                        | (
+                            Some(HeapValue::CheckedIndex(checked, bytes)),
+                            Some(HeapValue::HeapBase(memory)),
+                        ) if memory == checked => return *offset >= 0 && offset + width <= bytes,
+                        _ => (),
+                    }
+                }
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    // The heap base or heap address in `regnum`, if it is in a memory with
+    // guard regions.
+    fn guarded_heap_value(&self, state: &HeapLattice, regnum: &u8) -> Option<HeapValue> {
+        let v = state.regs.get(regnum, &ValSize::Size64).v;
+        match v {
+            Some(HeapValue::HeapBase(memory)) | Some(HeapValue::HeapAddr(memory))
+                if self.analyzer.has_guards(memory) =>
+            {
+                v
+            }
+            _ => None,
+        }
+    }
+
     fn check_heap_access(&self, state: &HeapLattice, access: &Value) -> bool {
-        if let Value::Mem(_, memargs) = access {
+        self.check_bounds_checked_heap_access(state, access)
+            || self.check_guarded_heap_access(state, access)
+    }
+
+    fn check_guarded_heap_access(&self, state: &HeapLattice, access: &Value) -> bool {
+        if let Value::Mem(size, memargs) = access {
+            let width = (size.to_u32() / 8) as i64;
             match memargs {
                 // if only arg is heapbase or heapaddr
                 MemArgs::Mem1Arg(MemArg::Reg(regnum, ValSize::Size64)) => {
-                    if let Some(HeapValue::HeapBase) = state.regs.get(regnum, &ValSize::Size64).v {
+                    if let Some(HeapValue::HeapBase(_)) = self.guarded_heap_value(state, regnum) {
                         return true;
                     }
-                    if let Some(HeapValue::HeapAddr) = state.regs.get(regnum, &ValSize::Size64).v {
-                        return true;
+                    if let Some(HeapValue::HeapAddr(_)) = self.guarded_heap_value(state, regnum) {
+                        return in_guard_region(0, width);
                     }
                 }
                 // if arg1 is heapbase and arg2 is bounded ||
                 // if arg1 is heapaddr and arg2 is constant offset
                 MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), memarg2) => {
-                    if let Some(HeapValue::HeapBase) = state.regs.get(regnum, &ValSize::Size64).v {
+                    if let Some(HeapValue::HeapBase(_)) = self.guarded_heap_value(state, regnum) {
                         match memarg2 {
                             MemArg::Reg(regnum2, size2) => {
                                 if let Some(HeapValue::Bounded4GB) =
                                     state.regs.get(regnum2, size2).v
                                 {
-                                    return true;
+                                    return in_guard_region(0, width);
                                 }
                             }
-                            MemArg::Imm(_, _, v) => return *v >= -0x1000 && *v <= 0xffffffff,
+                            MemArg::Imm(_, _, v) => return in_guard_region(*v, width),
                         }
                     }
-                    if let Some(HeapValue::HeapAddr) = state.regs.get(regnum, &ValSize::Size64).v {
+                    if let Some(HeapValue::HeapAddr(_)) = self.guarded_heap_value(state, regnum) {
                         match memarg2 {
-                            MemArg::Imm(_, _, v) => return *v >= -0x1000 && *v <= 0xffffffff,
+                            MemArg::Imm(_, _, v) => return in_guard_region(*v, width),
                             _ => {}
                         }
                     }
                 }
                 // if arg1 is heapbase and arg2 and arg3 are bounded ||
-                // if arg1 is bounded and arg1 and arg3 are bounded
+                // if arg1 is bounded and arg1 and arg3 are bounded ||
+                // if arg1 is heapaddr, arg2 is bounded and arg3 is constant
                 MemArgs::Mem3Args(MemArg::Reg(regnum, ValSize::Size64), memarg2, memarg3)
                 | MemArgs::Mem3Args(memarg2, MemArg::Reg(regnum, ValSize::Size64), memarg3) => {
-                    if let Some(HeapValue::HeapBase) = state.regs.get(regnum, &ValSize::Size64).v {
+                    if let Some(HeapValue::HeapBase(_)) = self.guarded_heap_value(state, regnum) {
                         match (memarg2, memarg3) {
                             (MemArg::Reg(regnum2, size2), MemArg::Imm(_, _, v))
                             | (MemArg::Imm(_, _, v), MemArg::Reg(regnum2, size2)) => {
                                 if let Some(HeapValue::Bounded4GB) =
                                     state.regs.get(regnum2, size2).v
                                 {
-                                    return *v <= 0xffffffff;
+                                    return in_guard_region(*v, width);
                                 }
                             }
                             (MemArg::Reg(regnum2, size2), MemArg::Reg(regnum3, size3)) => {
@@ -220,13 +369,48 @@ impl HeapChecker<'_> {
                                     state.regs.get(regnum2, size2).v,
                                     state.regs.get(regnum3, size3).v,
                                 ) {
-                                    return true;
+                                    return in_guard_region(MAX_BOUNDED_4GB, width);
                                 }
                             }
                             _ => (),
                         }
                     }
+                    // the last element of a string instruction's extent:
+                    // heapaddr + a Bounded4GB count + constant offset
+                    if let Some(HeapValue::HeapAddr(_)) = self.guarded_heap_value(state, regnum) {
+                        if let (MemArg::Reg(regnum2, size2), MemArg::Imm(_, _, v)) =
+                            (memarg2, memarg3)
+                        {
+                            if let Some(HeapValue::Bounded4GB) = state.regs.get(regnum2, size2).v {
+                                return in_guard_region(MAX_BOUNDED_4GB + v, width);
+                            }
+                        }
+                    }
+                }
+                // the last element of a rep string instruction's extent,
+                // heapaddr + count * scale + disp, if count * scale can't
+                // reach past the guard region
+                MemArgs::MemScaleDisp(
+                    MemArg::Reg(regnum, ValSize::Size64),
+                    MemArg::Reg(count, count_size),
+                    MemArg::Imm(_, _, scale),
+                    MemArg::Imm(_, _, disp),
+                ) => {
+                    let max_base = match self.guarded_heap_value(state, regnum) {
+                        Some(HeapValue::HeapBase(_)) => 0,
+                        Some(HeapValue::HeapAddr(_)) => MAX_BOUNDED_4GB,
+                        _ => return false,
+                    };
+                    let max_count = match state.regs.get(count, count_size).v {
+                        Some(HeapValue::Bounded4GBShr(bits)) => MAX_BOUNDED_4GB >> bits,
+                        _ => return false,
+                    };
+                    let lowest = std::cmp::min(0, max_count * scale) + disp;
+                    let highest = max_base + std::cmp::max(0, max_count * scale) + disp + width;
+                    return lowest >= -0x1000 && highest <= GUARDED_HEAP_RESERVATION;
                 }
+                // a Bounded4GB index times a scale can reach past the guard
+                // region, so scaled accesses aren't guarded heap accesses
                 _ => return false,
             }
         }
@@ -234,6 +418,7 @@ impl HeapChecker<'_> {
     }
 
     fn check_metadata_access(&self, state: &HeapLattice, access: &Value) -> bool {
+        let tables = &self.analyzer.metadata.tables;
         if let Value::Mem(_size, memargs) = access {
             match memargs {
                 //Case 1: mem[globals_base]
@@ -243,8 +428,11 @@ impl HeapChecker<'_> {
                         return true;
                     }
                 }
-                //Case 2: mem[lucet_tables + 8]
-                MemArgs::Mem2Args(MemArg::Reg(regnum, ValSize::Size64), MemArg::Imm(_, _, 8)) => {
+                //Case 2: mem[lucet_tables + table size offset]
+                MemArgs::Mem2Args(
+                    MemArg::Reg(regnum, ValSize::Size64),
+                    MemArg::Imm(_, _, offset),
+                ) if *offset == tables.size_offset => {
                     if let Some(HeapValue::LucetTables) = state.regs.get(regnum, &ValSize::Size64).v
                     {
                         return true;
@@ -268,8 +456,8 @@ impl HeapChecker<'_> {
                 MemArgs::Mem3Args(
                     MemArg::Reg(regnum1, ValSize::Size64),
                     MemArg::Reg(regnum2, ValSize::Size64),
-                    MemArg::Imm(_, _, 8),
-                ) => {
+                    MemArg::Imm(_, _, offset),
+                ) if *offset == tables.fn_ptr_offset => {
                     match (
                         state.regs.get(regnum1, &ValSize::Size64).v,
                         state.regs.get(regnum2, &ValSize::Size64).v,
@@ -279,23 +467,45 @@ impl HeapChecker<'_> {
                         _ => (),
                     }
                 }
+                MemArgs::MemScaleDisp(
+                    MemArg::Reg(regnum, ValSize::Size64),
+                    _,
+                    _,
+                    MemArg::Imm(_, _, offset),
+                ) if *offset == tables.fn_ptr_offset => {
+                    if let Some(HeapValue::GuestTable0) = state.regs.get(regnum, &ValSize::Size64).v
+                    {
+                        return true;
+                    }
+                }
                 _ => return false,
             }
         }
         false
     }
 
-    fn check_jump_table_access(&self, _state: &HeapLattice, access: &Value) -> bool {
+    fn check_jump_table_access(&self, state: &HeapLattice, access: &Value) -> bool {
         if let Value::Mem(_size, memargs) = access {
             match memargs {
-                MemArgs::MemScale(_, _, MemArg::Imm(_, _, 4)) => return true,
+                // The base must be the table address, from a `lea` of a
+                // rip-relative constant; any other base, such as the heap
+                // base, is not a jump table.
+                MemArgs::MemScale(
+                    MemArg::Reg(regnum, ValSize::Size64),
+                    _,
+                    MemArg::Imm(_, _, 4),
+                ) => {
+                    if let Some(HeapValue::RIPConst) = state.regs.get(regnum, &ValSize::Size64).v {
+                        return true;
+                    }
+                }
                 _ => return false,
             }
         }
         false
     }
 
-    fn check_mem_access(&self, state: &HeapLattice, access: &Value) -> bool {
+    fn check_mem_access(&self, state: &HeapLattice, access: &Value, is_write: bool) -> bool {
         // Case 1: its a stack access
         if is_stack_access(access) {
             return true;
@@ -304,6 +514,10 @@ impl HeapChecker<'_> {
         if is_frame_access(access) {
             return true;
         }
+        // Any other access of unknown width can't be shown to stay in bounds
+        if let Value::Mem(ValSize::SizeOther, _) = access {
+            return false;
+        }
         // Case 3: it is an access based at a constant loaded from
         // program data. We trust the compiler knows what it's doing
         // in such a case. This could also be a globals or table
@@ -328,7 +542,11 @@ impl HeapChecker<'_> {
         if self.check_jump_table_access(state, access) {
             return true;
         };
-        // Case 8: its unknown
+        // Case 8: its a VM context access
+        if self.check_vmctx_access(state, access, is_write) {
+            return true;
+        };
+        // Case 9: its unknown
         log::debug!("None of the memory accesses!");
         print_mem_access(state, access);
         return false;
@@ -342,6 +560,29 @@ pub fn memarg_repr(state: &HeapLattice, memarg: &MemArg) -> String {
     }
 }
 
+pub fn mem_access_operands(state: &HeapLattice, access: &Value) -> Vec<String> {
+    match access {
+        Value::Mem(_, MemArgs::Mem1Arg(x)) => vec![memarg_repr(state, x)],
+        Value::Mem(_, MemArgs::Mem2Args(x, y)) => {
+            vec![memarg_repr(state, x), memarg_repr(state, y)]
+        }
+        Value::Mem(_, MemArgs::Mem3Args(x, y, z)) | Value::Mem(_, MemArgs::MemScale(x, y, z)) => {
+            vec![
+                memarg_repr(state, x),
+                memarg_repr(state, y),
+                memarg_repr(state, z),
+            ]
+        }
+        Value::Mem(_, MemArgs::MemScaleDisp(w, x, y, z)) => vec![
+            memarg_repr(state, w),
+            memarg_repr(state, x),
+            memarg_repr(state, y),
+            memarg_repr(state, z),
+        ],
+        _ => vec![],
+    }
+}
+
 pub fn print_mem_access(state: &HeapLattice, access: &Value) {
     if let Value::Mem(_, memargs) = access {
         match memargs {
@@ -363,6 +604,191 @@ pub fn print_mem_access(state: &HeapLattice, access: &Value) {
                 memarg_repr(state, y),
                 memarg_repr(state, z)
             ),
+            MemArgs::MemScaleDisp(w, x, y, z) => log::debug!(
+                "mem[{:?} + {:?} * {:?} + {:?}]",
+                memarg_repr(state, w),
+                memarg_repr(state, x),
+                memarg_repr(state, y),
+                memarg_repr(state, z)
+            ),
         }
     }
 }
+
+#[test]
+fn guard_region_width_test() {
+    assert!(in_guard_region(0, 64));
+    assert!(in_guard_region(-0x1000, 8));
+    assert!(!in_guard_region(-0x1008, 8));
+    // the last 4 bytes of the guard region
+    assert!(in_guard_region(0xffff_fffd, 4));
+    assert!(!in_guard_region(0xffff_fffe, 4));
+    // a 32 byte load that starts inside the guard region but ends past it
+    assert!(!in_guard_region(0xffff_fff0, 32));
+}
+
+#[test]
+fn bounds_checked_width_test() {
+    use crate::utils::testing::heap_violations;
+
+    let violations = |lea_offset: i64, branch: &str| {
+        // idx + lea_offset <= bound on the fallthrough edge
+        heap_violations(
+            &format!(
+                "\
+block 0x0 -> 0xf, 0x17
+  0x0: r6:32 <- mov r6:32
+  0x2: r0:64 <- mov mem64[r7:64 + 72]
+  0x6: r2:64 <- add r6:64, {}:i32
+  0xa: r16:8 <- cmp r2:64, r0:64
+  0xd: br {} 23:i64
+block 0xf
+  0xf: r1:64 <- mov mem64[r7:64 + 64]
+  0x13: r0:32 <- mov mem32[r1:64 + r6:64]
+  0x16: ret
+block 0x17
+  0x17: undefined
+",
+                lea_offset, branch
+            ),
+            HeapStrategy::VMCtxFirstArgWithBoundsChecks {
+                vmctx_heap_base_ptr_offset: 0x40,
+                vmctx_heap_bound_offset: 0x48,
+            },
+        )
+    };
+    assert!(violations(4, "ja").is_empty());
+    assert!(violations(8, "ja").is_empty());
+    // the check covers 2 bytes of the 4 byte load
+    assert_eq!(violations(2, "ja")[0].addr, 0x13);
+    // in bounds only if the branch is taken
+    assert_eq!(violations(4, "jna")[0].addr, 0x13);
+}
+
+#[test]
+fn bounds_checked_memory64_test() {
+    use crate::utils::testing::heap_violations;
+
+    let violations = |end: i64, carry_branch: &str| {
+        // a 4 byte load at a 64-bit index + 8, checked with the index + end
+        heap_violations(
+            &format!(
+                "\
+block 0x0 -> 0xd, 0x1b
+  0x0: r0:64 <- mov mem64[r7:64 + 72]
+  0x4: r2:64 <- mov r6:64
+  0x7: r2:64 <- add r2:64, {end}:i32
+  0x7: r16:8 <- clear(r2:64, {end}:i32)
+  0xb: br {carry_branch} 27:i64
+block 0xd -> 0x12, 0x1b
+  0xd: r16:8 <- cmp r2:64, r0:64
+  0x10: br ja 27:i64
+block 0x12
+  0x12: r1:64 <- mov mem64[r7:64 + 64]
+  0x16: r0:32 <- mov mem32[r1:64 + r6:64 + 8]
+  0x1a: ret
+block 0x1b
+  0x1b: undefined
+",
+                end = end,
+                carry_branch = carry_branch
+            ),
+            HeapStrategy::VMCtxFirstArgWithBoundsChecks {
+                vmctx_heap_base_ptr_offset: 0x40,
+                vmctx_heap_bound_offset: 0x48,
+            },
+        )
+    };
+    assert!(violations(12, "jb").is_empty());
+    assert_eq!(violations(11, "jb")[0].addr, 0x16);
+    // the add may have wrapped around on the fallthrough
+    assert_eq!(violations(12, "jnb")[0].addr, 0x16);
+}
+
+#[test]
+fn indexed_stack_write_test() {
+    use crate::utils::testing::heap_violations;
+
+    let violations = |stmt: &str| {
+        // the heap base is spilled, then reloaded and used
+        heap_violations(
+            &format!(
+                "\
+block 0x0
+  0x0: r4:64 <- sub r4:64, 16:i32
+  0x4: mem64[r4:64 + 8] <- mov r7:64
+  0x9: {}
+  0xd: r2:64 <- mov mem64[r4:64 + 8]
+  0x12: r0:32 <- mov mem32[r2:64]
+  0x14: r4:64 <- add r4:64, 16:i32
+  0x18: ret
+",
+                stmt
+            ),
+            HeapStrategy::HeapPtrFirstArgWithGuards,
+        )
+    };
+    assert!(violations("r3:64 <- mov r0:64").is_empty());
+    // may overwrite the spilled heap base
+    assert_eq!(
+        violations("mem64[r4:64 + r1:64] <- mov r0:64")[0].addr,
+        0x12
+    );
+    assert_eq!(
+        violations("mem64[r4:64 + r1:64 * 8:i64] <- mov r0:64")[0].addr,
+        0x12
+    );
+}
+
+#[test]
+fn vmctx_store_test() {
+    use crate::utils::testing::heap_violations;
+
+    // the heap base field is overwritten with an index, then used
+    let violations = heap_violations(
+        "\
+block 0x0
+  0x0: mem64[r7:64 + 64] <- mov r6:64
+  0x4: r0:64 <- mov mem64[r7:64 + 64]
+  0x8: r1:32 <- mov mem32[r0:64]
+  0xa: r2:64 <- mov mem64[r7:64 + 4096]
+  0x11: ret
+",
+        HeapStrategy::VMCtxFirstArgWithGuards {
+            vmctx_heap_base_ptr_offset: 0x40,
+        },
+    );
+    // the store, and the read past the end of the vmctx
+    let addrs: Vec<u64> = violations.iter().map(|v| v.addr).collect();
+    assert_eq!(addrs, vec![0x0, 0xa]);
+}
+
+#[test]
+fn rep_count_shift_test() {
+    use crate::utils::testing::heap_violations;
+
+    // rep stosq of a 32-bit byte count shifted into an element count
+    let violations = |shift: i64| {
+        heap_violations(
+            &format!(
+                "\
+block 0x0
+  0x0: r0:32 <- mov r6:32
+  0x2: r7:64 <- add r7:64, r0:64
+  0x5: r1:32 <- mov r2:32
+  0x7: r1:64 <- shr r1:64, {}:i8
+  0xb: mem64[r7:64] <- clear(r0:64)
+  0xb: mem64[r7:64 + r1:64 * 8:i64 + -8:i64] <- clear(r0:64)
+  0xb: r7:64 <- clear()
+  0xb: r1:64 <- clear()
+  0xe: ret
+",
+                shift
+            ),
+            HeapStrategy::HeapPtrFirstArgWithGuards,
+        )
+    };
+    assert!(violations(3).is_empty());
+    // 8 * a 31-bit count can reach past the guard region
+    assert_eq!(violations(1)[0].addr, 0xb);
+}
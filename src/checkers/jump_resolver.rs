@@ -1,34 +1,40 @@
-use crate::analyses::jump_analyzer::SwitchAnalyzer;
+use crate::analyses::jump_analyzer::{analyze_jumps, SwitchAnalyzer};
+use crate::analyses::reaching_defs::{analyze_reaching_defs, ReachingDefnAnalyzer};
 use crate::analyses::{AbstractAnalyzer, AnalysisResult};
+use crate::checkers::{Violation, ViolationKind};
 use crate::lattices::reachingdefslattice::LocIdx;
 use crate::lattices::switchlattice::{SwitchLattice, SwitchValue, SwitchValueLattice};
 use crate::utils::lifter::{IRMap, Stmt, Value};
-use std::collections::HashMap;
+use crate::utils::utils::LucetMetadata;
+use std::collections::{BTreeSet, HashMap};
+use yaxpeax_core::analyses::control_flow::VW_CFG;
 use yaxpeax_core::memory::repr::process::ModuleData;
 use yaxpeax_core::memory::MemoryRepr;
 
-fn load_target(program: &ModuleData, addr: u64) -> i64 {
-    let b0 = program.read(addr).unwrap() as u32;
-    let b1 = (program.read(addr + 1).unwrap() as u32) << 8;
-    let b2 = (program.read(addr + 2).unwrap() as u32) << 16;
-    let b3 = (program.read(addr + 3).unwrap() as u32) << 24;
-    (b0 + b1 + b2 + b3) as i64
+// `None` if the entry is outside the module, e.g. past the end of a
+// single function checked on its own.
+fn load_target(program: &ModuleData, addr: u64) -> Option<i64> {
+    let b0 = program.read(addr)? as u32;
+    let b1 = (program.read(addr + 1)? as u32) << 8;
+    let b2 = (program.read(addr + 2)? as u32) << 16;
+    let b3 = (program.read(addr + 3)? as u32) << 24;
+    Some((b0 + b1 + b2 + b3) as i64)
 }
 
-fn extract_jmp_targets(program: &ModuleData, aval: &SwitchValueLattice) -> Vec<i64> {
+fn extract_jmp_targets(program: &ModuleData, aval: &SwitchValueLattice) -> Option<Vec<i64>> {
     let mut targets: Vec<i64> = Vec::new();
     match aval.v {
         Some(SwitchValue::JmpTarget(base, upper_bound)) => {
             for idx in 0..upper_bound {
                 let addr = base + idx * 4;
-                let target = load_target(program, addr.into());
+                let target = load_target(program, addr.into())?;
                 let resolved_target = ((base as i32) + (target as i32)) as i64;
                 targets.push(resolved_target);
             }
         }
-        _ => panic!("Jump Targets Broken, target = {:?}", aval.v),
+        _ => return None,
     }
-    targets
+    Some(targets)
 }
 
 // addr -> vec of targets
@@ -61,11 +67,16 @@ pub fn resolve_jumps(
                 match ir_stmt {
                     Stmt::Branch(_, Value::Reg(regnum, regsize)) => {
                         let aval = state.regs.get(regnum, regsize);
-                        let targets = extract_jmp_targets(program, &aval);
-                        switch_targets.insert(*addr, targets);
+                        match extract_jmp_targets(program, &aval) {
+                            Some(targets) => {
+                                switch_targets.insert(*addr, targets);
+                            }
+                            // left unresolved, and reported by `check_jumps`
+                            None => log::debug!("Jump Targets Broken, target = {:?}", aval.v),
+                        }
                     }
                     Stmt::Branch(_, Value::Mem(_, _)) => {
-                        panic!("Illegal Jump!");
+                        log::debug!("Illegal Jump!");
                     }
                     _ => (),
                 }
@@ -83,3 +94,105 @@ pub fn resolve_jumps(
     }
     switch_targets
 }
+
+/// Reports every indirect jump that jump resolution left without successors.
+/// The analyses never follow such a jump, so the function can't be trusted.
+pub fn check_jumps(cfg: &VW_CFG, irmap: &IRMap, func: &str) -> Vec<Violation> {
+    let mut violations = vec![];
+    for (block_addr, ir_block) in irmap {
+        if cfg.graph.neighbors(*block_addr).next().is_some() {
+            continue;
+        }
+        for (addr, ir_stmts) in ir_block {
+            for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
+                let target = match ir_stmt {
+                    Stmt::Branch(_, target @ Value::Reg(_, _))
+                    | Stmt::Branch(_, target @ Value::Mem(_, _)) => target,
+                    _ => continue,
+                };
+                violations.push(Violation {
+                    func: func.to_string(),
+                    block_addr: *block_addr,
+                    addr: *addr,
+                    idx: idx as u32,
+                    stmt: ir_stmt.clone(),
+                    operands: vec![format!("{:?}", target)],
+                    kind: ViolationKind::UnresolvedIndirectJump,
+                });
+            }
+        }
+    }
+    violations.sort_by_key(|v| (v.addr, v.idx, v.kind));
+    violations
+}
+
+/// Checks the indirect jumps of a function whose CFG was supplied by its
+/// compiler rather than recovered. Each must be a bounds-checked lookup in
+/// a jump table inside the function, and the block's edges must be exactly
+/// the table's targets, so a wrong edge can't hide a jump out of the
+/// function from the other analyses.
+pub fn check_jump_tables(
+    program: &ModuleData,
+    cfg: &VW_CFG,
+    irmap: &IRMap,
+    metadata: &LucetMetadata,
+    func: &str,
+) -> Vec<Violation> {
+    let switch_analyzer = SwitchAnalyzer {
+        metadata: metadata.clone(),
+        reaching_defs: analyze_reaching_defs(cfg, irmap, metadata.clone()),
+        reaching_analyzer: ReachingDefnAnalyzer {
+            cfg: cfg.clone(),
+            irmap: irmap.clone(),
+        },
+    };
+    let switch_results = analyze_jumps(cfg, irmap, &switch_analyzer);
+    let switch_targets = resolve_jumps(program, switch_results, irmap, &switch_analyzer);
+
+    let mut violations = vec![];
+    for (block_addr, ir_block) in irmap {
+        for (addr, ir_stmts) in ir_block {
+            for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
+                match ir_stmt {
+                    Stmt::Branch(_, Value::Reg(_, _)) | Stmt::Branch(_, Value::Mem(_, _)) => (),
+                    _ => continue,
+                }
+                let mut violation = Violation {
+                    func: func.to_string(),
+                    block_addr: *block_addr,
+                    addr: *addr,
+                    idx: idx as u32,
+                    stmt: ir_stmt.clone(),
+                    operands: vec![],
+                    kind: ViolationKind::UnresolvedIndirectJump,
+                };
+                let targets = match switch_targets.get(addr) {
+                    Some(targets) => targets,
+                    None => {
+                        violations.push(violation);
+                        continue;
+                    }
+                };
+                let targets: BTreeSet<i64> = targets.iter().cloned().collect();
+                let edges: BTreeSet<i64> = cfg
+                    .graph
+                    .neighbors(*block_addr)
+                    .map(|succ| succ as i64)
+                    .collect();
+                let in_function = targets
+                    .iter()
+                    .all(|target| *target >= 0 && cfg.blocks.contains_key(&(*target as u64)));
+                if targets != edges || !in_function {
+                    violation.kind = ViolationKind::JumpTableEdgeMismatch;
+                    violation.operands = vec![
+                        format!("targets: {:x?}", targets),
+                        format!("edges: {:x?}", edges),
+                    ];
+                    violations.push(violation);
+                }
+            }
+        }
+    }
+    violations.sort_by_key(|v| (v.addr, v.idx, v.kind));
+    violations
+}
@@ -3,18 +3,155 @@ use crate::lattices::reachingdefslattice::LocIdx;
 use crate::lattices::Lattice;
 use crate::utils::lifter::IRMap;
 use crate::utils::lifter::Stmt;
+use serde::{Deserialize, Serialize};
 
 pub mod call_checker;
 pub mod heap_checker;
 pub mod jump_resolver;
 pub mod stack_checker;
 
+/// The safety property a checker establishes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Property {
+    Stack,
+    Heap,
+    Call,
+    Jump,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    // stack checker
+    StackGrowthUnknown,
+    StackGrowthPositive,
+    StackFrameNotProbed,
+    StackReadOutOfBounds,
+    StackWriteOutOfBounds,
+    FrameReadOutOfBounds,
+    FrameWriteOutOfBounds,
+    StackNotRestoredAtReturn,
+    // heap checker
+    HeapBaseNotPassedToCall,
+    UncheckedMemoryAccess,
+    // call checker
+    UnresolvedIndirectCall,
+    UncheckedCallTableLookup,
+    // jump resolver
+    UnresolvedIndirectJump,
+    JumpTableEdgeMismatch,
+}
+
+impl ViolationKind {
+    pub const ALL: [ViolationKind; 14] = [
+        ViolationKind::StackGrowthUnknown,
+        ViolationKind::StackGrowthPositive,
+        ViolationKind::StackFrameNotProbed,
+        ViolationKind::StackReadOutOfBounds,
+        ViolationKind::StackWriteOutOfBounds,
+        ViolationKind::FrameReadOutOfBounds,
+        ViolationKind::FrameWriteOutOfBounds,
+        ViolationKind::StackNotRestoredAtReturn,
+        ViolationKind::HeapBaseNotPassedToCall,
+        ViolationKind::UncheckedMemoryAccess,
+        ViolationKind::UnresolvedIndirectCall,
+        ViolationKind::UncheckedCallTableLookup,
+        ViolationKind::UnresolvedIndirectJump,
+        ViolationKind::JumpTableEdgeMismatch,
+    ];
+
+    pub fn property(&self) -> Property {
+        match self {
+            ViolationKind::StackGrowthUnknown
+            | ViolationKind::StackGrowthPositive
+            | ViolationKind::StackFrameNotProbed
+            | ViolationKind::StackReadOutOfBounds
+            | ViolationKind::StackWriteOutOfBounds
+            | ViolationKind::FrameReadOutOfBounds
+            | ViolationKind::FrameWriteOutOfBounds
+            | ViolationKind::StackNotRestoredAtReturn => Property::Stack,
+            ViolationKind::HeapBaseNotPassedToCall | ViolationKind::UncheckedMemoryAccess => {
+                Property::Heap
+            }
+            ViolationKind::UnresolvedIndirectCall | ViolationKind::UncheckedCallTableLookup => {
+                Property::Call
+            }
+            ViolationKind::UnresolvedIndirectJump | ViolationKind::JumpTableEdgeMismatch => {
+                Property::Jump
+            }
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ViolationKind::StackGrowthUnknown => "stack growth is unknown",
+            ViolationKind::StackGrowthPositive => "stack pointer is above the frame start",
+            ViolationKind::StackFrameNotProbed => {
+                "stack frame allocation skips the guard page below the probed stack"
+            }
+            ViolationKind::StackReadOutOfBounds => "stack read is out of bounds",
+            ViolationKind::StackWriteOutOfBounds => "stack write is out of bounds",
+            ViolationKind::FrameReadOutOfBounds => "frame (rbp) read is out of bounds",
+            ViolationKind::FrameWriteOutOfBounds => "frame (rbp) write is out of bounds",
+            ViolationKind::StackNotRestoredAtReturn => "stack growth is not zero at return",
+            ViolationKind::HeapBaseNotPassedToCall => "heap base is not passed in rdi at call",
+            ViolationKind::UncheckedMemoryAccess => {
+                "memory access base is not HeapBase/HeapAddr or another trusted base"
+            }
+            ViolationKind::UnresolvedIndirectCall => "indirect call target is not a checked FnPtr",
+            ViolationKind::UncheckedCallTableLookup => "call table lookup uses an unchecked index",
+            ViolationKind::UnresolvedIndirectJump => "indirect jump target could not be resolved",
+            ViolationKind::JumpTableEdgeMismatch => {
+                "jump table targets do not match the block's CFG edges"
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// A single failed check, along with enough context to triage it without
+/// rerunning the analysis.
+#[derive(Clone, Debug)]
+pub struct Violation {
+    pub func: String,
+    pub block_addr: u64,
+    pub addr: u64,
+    pub idx: u32,
+    pub stmt: Stmt,
+    /// Abstract values of the operands involved, e.g. `r7: Some(HeapBase)`.
+    pub operands: Vec<String>,
+    pub kind: ViolationKind,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at 0x{:x} (block 0x{:x}): {}: {:?} [{}]",
+            self.func,
+            self.addr,
+            self.block_addr,
+            self.kind,
+            self.stmt,
+            self.operands.join(", ")
+        )
+    }
+}
+
 pub trait Checker<State: Lattice + Clone> {
-    fn check(&self, result: AnalysisResult<State>) -> bool;
+    fn check(&self, result: AnalysisResult<State>) -> Vec<Violation>;
     fn irmap(&self) -> &IRMap;
+    fn func_name(&self) -> &str;
     fn aexec(&self, state: &mut State, ir_stmt: &Stmt, loc: &LocIdx);
 
-    fn check_state_at_statements(&self, result: AnalysisResult<State>) -> bool {
+    fn check_state_at_statements(&self, result: AnalysisResult<State>) -> Vec<Violation> {
+        let mut violations = vec![];
         for (block_addr, mut state) in result {
             log::debug!(
                 "Checking block 0x{:x} with start state {:?}",
@@ -29,28 +166,37 @@ pub trait Checker<State: Lattice + Clone> {
                         ir_stmt,
                         state
                     );
-                    if !self.check_statement(
-                        &state,
-                        ir_stmt,
-                        &LocIdx {
+                    let loc_idx = LocIdx {
+                        addr: *addr,
+                        idx: idx as u32,
+                    };
+                    for (kind, operands) in self.check_statement(&state, ir_stmt, &loc_idx) {
+                        log::debug!("0x{:x}: {}: {:?}", addr, kind, operands);
+                        violations.push(Violation {
+                            func: self.func_name().to_string(),
+                            block_addr,
                             addr: *addr,
                             idx: idx as u32,
-                        },
-                    ) {
-                        return false;
+                            stmt: ir_stmt.clone(),
+                            operands,
+                            kind,
+                        });
                     }
-                    self.aexec(
-                        &mut state,
-                        ir_stmt,
-                        &LocIdx {
-                            addr: *addr,
-                            idx: idx as u32,
-                        },
-                    );
+                    self.aexec(&mut state, ir_stmt, &loc_idx);
                 }
             }
         }
-        true
+        // `result` is a HashMap, so order by location to keep reports stable.
+        violations.sort_by_key(|v| (v.addr, v.idx, v.kind));
+        violations
     }
-    fn check_statement(&self, state: &State, ir_stmt: &Stmt, loc_idx: &LocIdx) -> bool;
+
+    /// Returns every check that fails at `ir_stmt`, along with the abstract
+    /// values of the operands involved.
+    fn check_statement(
+        &self,
+        state: &State,
+        ir_stmt: &Stmt,
+        loc_idx: &LocIdx,
+    ) -> Vec<(ViolationKind, Vec<String>)>;
 }
@@ -1,36 +1,54 @@
-use crate::analyses::stack_analyzer::StackAnalyzer;
+use crate::analyses::stack_analyzer::{skips_guard_page, StackAnalyzer};
 use crate::analyses::{AbstractAnalyzer, AnalysisResult};
-use crate::checkers::Checker;
+use crate::checkers::{Checker, Violation, ViolationKind};
 use crate::lattices::reachingdefslattice::LocIdx;
 use crate::lattices::stackgrowthlattice::StackGrowthLattice;
-use crate::utils::ir_utils::{get_imm_mem_offset, is_bp_access, is_stack_access};
-use crate::utils::lifter::{IRMap, MemArgs, Stmt, Value};
+use crate::utils::ir_utils::{extract_stack_offset, is_bp_access, is_rsp, is_stack_access};
+use crate::utils::lifter::{Binopcode, IRMap, Stmt, ValSize, Value};
 
 pub struct StackChecker<'a> {
     irmap: &'a IRMap,
     analyzer: &'a StackAnalyzer,
+    func: &'a str,
 }
 
 pub fn check_stack(
     result: AnalysisResult<StackGrowthLattice>,
     irmap: &IRMap,
     analyzer: &StackAnalyzer,
-) -> bool {
+    func: &str,
+) -> Vec<Violation> {
     StackChecker {
         irmap: irmap,
         analyzer: analyzer,
+        func: func,
     }
     .check(result)
 }
 
+fn stack_operands(state: &StackGrowthLattice, access: &Value) -> Vec<String> {
+    vec![
+        format!("{:?}", access),
+        format!(
+            "stackgrowth: {:?} probestack: {:?} rbp: {:?}",
+            state.get_stackgrowth(),
+            state.get_probestack(),
+            state.get_rbp()
+        ),
+    ]
+}
+
 impl Checker<StackGrowthLattice> for StackChecker<'_> {
-    fn check(&self, result: AnalysisResult<StackGrowthLattice>) -> bool {
+    fn check(&self, result: AnalysisResult<StackGrowthLattice>) -> Vec<Violation> {
         self.check_state_at_statements(result)
     }
 
     fn irmap(&self) -> &IRMap {
         self.irmap
     }
+    fn func_name(&self) -> &str {
+        self.func
+    }
     fn aexec(&self, state: &mut StackGrowthLattice, ir_stmt: &Stmt, loc: &LocIdx) {
         self.analyzer.aexec(state, ir_stmt, loc)
     }
@@ -40,156 +58,258 @@ impl Checker<StackGrowthLattice> for StackChecker<'_> {
         state: &StackGrowthLattice,
         ir_stmt: &Stmt,
         _loc_idx: &LocIdx,
-    ) -> bool {
+    ) -> Vec<(ViolationKind, Vec<String>)> {
         //1, stackgrowth is never Bottom or >= 0
         match state.v {
             None => {
-                println!("Failure Case at {:?}: Stackgrowth = None", ir_stmt);
-                return false;
+                log::debug!("Failure Case at {:?}: Stackgrowth = None", ir_stmt);
+                return vec![(ViolationKind::StackGrowthUnknown, vec![])];
             }
             Some((stackgrowth, _, _)) => {
                 if stackgrowth > 0 {
-                    return false;
+                    return vec![(
+                        ViolationKind::StackGrowthPositive,
+                        vec![format!("stackgrowth: {:?}", stackgrowth)],
+                    )];
                 }
             }
         }
 
-        // 2. Reads and writes are in bounds
-        match ir_stmt {
-            //encapsulates both load and store
-            Stmt::Unop(_, dst, src) =>
-            // stack write: probestack <= stackgrowth + c < 0
-            {
-                if is_stack_access(dst) {
-                    if !self.check_stack_write(state, dst) {
-                        log::debug!(
-                            "check_stack_write failed: access = {:?} state = {:?}",
-                            dst,
-                            state
-                        );
-                        return false;
-                    }
+        let mut violations = vec![];
+        // 2. Frame allocations don't skip the guard page
+        if let (
+            Stmt::Binop(Binopcode::Sub, dst, src1, Value::Imm(_, _, offset)),
+            Some((stackgrowth, probestack, _)),
+        ) = (ir_stmt, state.v)
+        {
+            if is_rsp(dst) && is_rsp(src1) && skips_guard_page(*offset, stackgrowth, probestack) {
+                violations.push((
+                    ViolationKind::StackFrameNotProbed,
+                    vec![
+                        format!("frame size: {:?}", offset),
+                        format!("probestack: {:?}", probestack),
+                    ],
+                ));
+            }
+        }
+
+        // 3. Reads and writes are in bounds
+        let (writes, reads): (Vec<&Value>, Vec<&Value>) = match ir_stmt {
+            Stmt::Unop(_, dst, src) => (vec![dst], vec![src]),
+            Stmt::Binop(_, dst, src1, src2) => (vec![dst], vec![src1, src2]),
+            Stmt::Clear(dst, srcs) => (vec![dst], srcs.iter().collect()),
+            _ => (vec![], vec![]),
+        };
+        // stack write: -probestack <= stackgrowth + c, stackgrowth + c + width <= 0
+        for dst in writes {
+            if is_stack_access(dst) {
+                if !self.check_stack_write(state, dst) {
+                    violations.push((
+                        ViolationKind::StackWriteOutOfBounds,
+                        stack_operands(state, dst),
+                    ));
+                }
+            }
+            if is_bp_access(dst) {
+                if !self.check_bp_write(state, dst) {
+                    violations.push((
+                        ViolationKind::FrameWriteOutOfBounds,
+                        stack_operands(state, dst),
+                    ));
                 }
-                if is_bp_access(dst) {
-                    if !self.check_bp_write(state, dst) {
-                        log::debug!(
-                            "check_bp_write failed: access = {:?} state = {:?}",
-                            dst,
-                            state
-                        );
-                        return false;
-                    }
+            }
+        }
+        // stack read: -probestack <= stackgrowth + c, stackgrowth + c + width <= 8K
+        for src in reads {
+            if is_stack_access(src) {
+                if !self.check_stack_read(state, src) {
+                    violations.push((
+                        ViolationKind::StackReadOutOfBounds,
+                        stack_operands(state, src),
+                    ));
                 }
-                //stack read: probestack <= stackgrowth + c < 8K
-                if is_stack_access(src) {
-                    if !self.check_stack_read(state, src) {
-                        log::debug!(
-                            "check_stack_read failed: access = {:?} state = {:?}",
-                            src,
-                            state
-                        );
-                        return false;
-                    }
-                } else if is_bp_access(src) {
-                    if !self.check_bp_read(state, src) {
-                        log::debug!(
-                            "check_bp_read failed: access = {:?} state = {:?}",
-                            src,
-                            state
-                        );
-                        return false;
-                    }
+            } else if is_bp_access(src) {
+                if !self.check_bp_read(state, src) {
+                    violations.push((
+                        ViolationKind::FrameReadOutOfBounds,
+                        stack_operands(state, src),
+                    ));
                 }
             }
-            _ => (),
         }
 
-        // 3. For all rets stackgrowth = 0
+        // 4. For all rets stackgrowth = 0
         if let Stmt::Ret = ir_stmt {
             if let Some((stackgrowth, _, _)) = state.v {
                 if stackgrowth != 0 {
-                    log::debug!("stackgrowth != 0 at ret: stackgrowth = {:?}", stackgrowth);
-                    return false;
+                    violations.push((
+                        ViolationKind::StackNotRestoredAtReturn,
+                        vec![format!("stackgrowth: {:?}", stackgrowth)],
+                    ));
                 }
             }
         }
 
-        true
+        violations
+    }
+}
+
+// The offset of an access from rsp or rbp and its width in bytes, or None
+// if it has no single known width or is not at a constant offset from the
+// 64-bit register.
+fn frame_offset(access: &Value) -> Option<(i64, i64)> {
+    match access {
+        Value::Mem(ValSize::SizeOther, _) => None,
+        Value::Mem(size, memargs) => {
+            extract_stack_offset(memargs).map(|offset| (offset, (size.to_u32() / 8) as i64))
+        }
+        _ => None,
     }
 }
 
 impl StackChecker<'_> {
     fn check_stack_read(&self, state: &StackGrowthLattice, src: &Value) -> bool {
-        if let Value::Mem(_, memargs) = src {
-            match memargs {
-                MemArgs::Mem1Arg(_memarg) => {
-                    return (-state.get_probestack().unwrap() <= state.get_stackgrowth().unwrap())
-                        && (state.get_stackgrowth().unwrap() < 8096)
-                }
-                MemArgs::Mem2Args(_memarg1, memarg2) => {
-                    let offset = get_imm_mem_offset(memarg2);
-                    return (-state.get_probestack().unwrap()
-                        <= state.get_stackgrowth().unwrap() + offset)
-                        && (state.get_stackgrowth().unwrap() + offset < 8096);
-                }
-                _ => return false, //stack accesses should never have 3 args
+        match frame_offset(src) {
+            Some((offset, width)) => {
+                (-state.get_probestack().unwrap() <= state.get_stackgrowth().unwrap() + offset)
+                    && (state.get_stackgrowth().unwrap() + offset + width <= 8096)
             }
+            None => false,
         }
-        panic!("Unreachable")
     }
 
     fn check_bp_read(&self, state: &StackGrowthLattice, src: &Value) -> bool {
-        if let Value::Mem(_, memargs) = src {
-            match memargs {
-                MemArgs::Mem1Arg(_memarg) => {
-                    return (-state.get_probestack().unwrap() <= state.get_rbp().unwrap())
-                        && (state.get_rbp().unwrap() < 8096)
-                }
-                MemArgs::Mem2Args(_memarg1, memarg2) => {
-                    let offset = get_imm_mem_offset(memarg2);
-                    return (-state.get_probestack().unwrap() <= state.get_rbp().unwrap() + offset)
-                        && (state.get_rbp().unwrap() + offset < 8096);
-                }
-                _ => return false, //stack accesses should never have 3 args
+        match frame_offset(src) {
+            Some((offset, width)) => {
+                (-state.get_probestack().unwrap() <= state.get_rbp().unwrap() + offset)
+                    && (state.get_rbp().unwrap() + offset + width <= 8096)
             }
+            None => false,
         }
-        panic!("Unreachable")
     }
 
     fn check_stack_write(&self, state: &StackGrowthLattice, dst: &Value) -> bool {
-        if let Value::Mem(_, memargs) = dst {
-            match memargs {
-                MemArgs::Mem1Arg(_memarg) => {
-                    return (-state.get_probestack().unwrap() <= state.get_stackgrowth().unwrap())
-                        && (state.get_stackgrowth().unwrap() < 0);
-                }
-                MemArgs::Mem2Args(_memarg1, memarg2) => {
-                    let offset = get_imm_mem_offset(memarg2);
-                    return (-state.get_probestack().unwrap()
-                        <= state.get_stackgrowth().unwrap() + offset)
-                        && (state.get_stackgrowth().unwrap() + offset < 0);
-                }
-                _ => return false, //stack accesses should never have 3 args
+        match frame_offset(dst) {
+            Some((offset, width)) => {
+                (-state.get_probestack().unwrap() <= state.get_stackgrowth().unwrap() + offset)
+                    && (state.get_stackgrowth().unwrap() + offset + width <= 0)
             }
+            None => false,
         }
-        panic!("Unreachable")
     }
 
     fn check_bp_write(&self, state: &StackGrowthLattice, dst: &Value) -> bool {
-        if let Value::Mem(_, memargs) = dst {
-            match memargs {
-                MemArgs::Mem1Arg(_memarg) => {
-                    return (-state.get_probestack().unwrap() <= state.get_rbp().unwrap())
-                        && (state.get_rbp().unwrap() < 0);
-                }
-                MemArgs::Mem2Args(_memarg1, memarg2) => {
-                    let offset = get_imm_mem_offset(memarg2);
-                    return (-state.get_probestack().unwrap() <= state.get_rbp().unwrap() + offset)
-                        && (state.get_rbp().unwrap() + offset < 0);
-                }
-                _ => return false, //stack accesses should never have 3 args
+        match frame_offset(dst) {
+            Some((offset, width)) => {
+                (-state.get_probestack().unwrap() <= state.get_rbp().unwrap() + offset)
+                    && (state.get_rbp().unwrap() + offset + width <= 0)
             }
+            None => false,
         }
-        panic!("Unreachable")
     }
 }
+
+#[test]
+fn stack_frame_not_probed_test() {
+    use crate::utils::testing::stack_violations;
+
+    // a 12K frame with no call to the stack probe
+    let violations = stack_violations(
+        "\
+block 0x0
+  0x0: r4:64 <- sub r4:64, 12288:i32
+  0x7: r4:64 <- add r4:64, 12288:i32
+  0xe: ret
+",
+    );
+    assert_eq!(violations[0].kind, ViolationKind::StackFrameNotProbed);
+    assert_eq!(violations[0].addr, 0);
+}
+
+#[test]
+fn stack_unknown_width_test() {
+    use crate::utils::testing::stack_violations;
+
+    // a store the decoder has no width for, inside the frame
+    let violations = stack_violations(
+        "\
+block 0x0
+  0x0: r4:64 <- sub r4:64, 16:i32
+  0x4: mem?[r4:64 + 8:i32] <- clear()
+  0x9: r4:64 <- add r4:64, 16:i32
+  0xd: ret
+",
+    );
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].kind, ViolationKind::StackWriteOutOfBounds);
+    assert_eq!(violations[0].addr, 4);
+}
+
+#[test]
+fn stack_indexed_access_test() {
+    use crate::utils::testing::stack_violations;
+
+    // a store and a load at a register offset from rsp
+    let violations = stack_violations(
+        "\
+block 0x0
+  0x0: r4:64 <- sub r4:64, 16:i32
+  0x4: mem64[r4:64 + r1:64] <- mov r0:64
+  0x8: r0:64 <- mov mem64[r4:64 + r1:64 * 8:i64]
+  0xc: r4:64 <- add r4:64, 16:i32
+  0x10: ret
+",
+    );
+    assert_eq!(violations.len(), 2);
+    assert_eq!(violations[0].kind, ViolationKind::StackWriteOutOfBounds);
+    assert_eq!(violations[1].kind, ViolationKind::StackReadOutOfBounds);
+}
+
+#[test]
+fn stack_write_width_test() {
+    use crate::utils::testing::stack_violations;
+
+    let violations = |store: &str| {
+        stack_violations(&format!(
+            "\
+block 0x0
+  0x0: r4:64 <- sub r4:64, 16:i32
+  0x4: {}
+  0x9: r4:64 <- add r4:64, 16:i32
+  0xd: ret
+",
+            store
+        ))
+    };
+    assert!(violations("mem64[r4:64 + 8:i32] <- mov r0:64").is_empty());
+    // the last 8 bytes of the store overwrite the return address
+    let violations = violations("mem128[r4:64 + 8:i32] <- clear(r0:128)");
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].kind, ViolationKind::StackWriteOutOfBounds);
+}
+
+#[test]
+fn stack_lea_test() {
+    use crate::utils::testing::stack_violations;
+
+    // lea rax, [rsp + 8]; lea rbp, [rsp + 8]; a store through rbp
+    let violations = |store: &str| {
+        stack_violations(&format!(
+            "\
+block 0x0
+  0x0: r4:64 <- sub r4:64, 16:i32
+  0x4: r0:64 <- add r4:64, 8:i32
+  0x9: r5:64 <- add r4:64, 8:i32
+  0xe: {}
+  0x12: r4:64 <- add r4:64, 16:i32
+  0x16: ret
+",
+            store
+        ))
+    };
+    assert!(violations("mem64[r5:64 + 0] <- mov r0:64").is_empty());
+    // rbp is 8 bytes below the return address
+    let violations = violations("mem64[r5:64 + 8] <- mov r0:64");
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].kind, ViolationKind::FrameWriteOutOfBounds);
+}
@@ -1,14 +1,28 @@
 use crate::lattices::{ConstLattice, VariableState};
 
+/// Index of a linear memory, for multi-memory modules. Single-memory
+/// strategies only use memory 0.
+pub type MemoryIdx = u32;
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum HeapValue {
-    HeapBase,
+    HeapBase(MemoryIdx),
     Bounded4GB,
-    HeapAddr,
+    Bounded4GBShr(u8), // a Bounded4GB value shifted right by this many bits
+    HeapAddr(MemoryIdx),
     LucetTables,
     GuestTable0,
     GlobalsBase,
     RIPConst,
+    VMCtxPtr,
+    // bounds-checked heaps
+    HeapBound(MemoryIdx), // heap length; the dynamic memory length for memory64
+    IndexPlus(u8, i64),   // a register plus a constant, without wrapping around
+    WrappingIndexPlus(u8, i64), // a register plus a constant, mod 2^64, from `add`
+    CarryFlag(u8),        // flags from `add reg, c` of a WrappingIndexPlus
+    BoundsCheckFlag(u8, MemoryIdx, i64), // flags from `cmp reg + c, HeapBound`
+    CheckedIndex(MemoryIdx, i64), // index + c <= the heap bound
+    CheckedAddr(MemoryIdx, i64), // heapbase + a CheckedIndex with the same c
 }
 
 pub type HeapValueLattice = ConstLattice<HeapValue>;
@@ -22,10 +36,10 @@ fn heap_lattice_test() {
 
     let x1 = HeapValueLattice { v: None };
     let x2 = HeapValueLattice {
-        v: Some(HeapValue::HeapBase),
+        v: Some(HeapValue::HeapBase(0)),
     };
     let x3 = HeapValueLattice {
-        v: Some(HeapValue::HeapBase),
+        v: Some(HeapValue::HeapBase(0)),
     };
     let x4 = HeapValueLattice {
         v: Some(HeapValue::Bounded4GB),
@@ -54,7 +68,7 @@ fn heap_lattice_test() {
     assert_eq!(
         x2.meet(&x3, &LocIdx { addr: 0, idx: 0 })
             == HeapValueLattice {
-                v: Some(HeapValue::HeapBase)
+                v: Some(HeapValue::HeapBase(0))
             },
         true
     );
@@ -9,7 +9,7 @@ pub mod switchlattice;
 use crate::lattices::reachingdefslattice::LocIdx;
 use crate::lattices::regslattice::X86RegsLattice;
 use crate::lattices::stacklattice::StackLattice;
-use crate::utils::ir_utils::{get_imm_offset, is_rsp};
+use crate::utils::ir_utils::{extract_stack_offset, is_rsp, is_stack_access};
 use crate::utils::lifter::{Binopcode, MemArg, MemArgs, ValSize, Value};
 use std::cmp::Ordering;
 use std::fmt::Debug;
@@ -129,27 +129,19 @@ impl<T: Lattice + Clone> VarState for VariableState<T> {
     type Var = T;
     fn set(&mut self, index: &Value, value: T) -> () {
         match index {
-            Value::Mem(memsize, memargs) => match memargs {
-                MemArgs::Mem1Arg(arg) => {
-                    if let MemArg::Reg(regnum, _) = arg {
-                        if *regnum == 4 {
-                            self.stack.update(0, value, memsize.to_u32() / 8)
-                        }
+            Value::Mem(memsize, memargs) if is_stack_access(index) => {
+                match extract_stack_offset(memargs) {
+                    Some(offset) if *memsize != ValSize::SizeOther => {
+                        self.stack.update(offset, value, memsize.to_u32() / 8)
                     }
+                    // an indexed write, or one of unknown width, may
+                    // overwrite any slot
+                    _ => self.stack.map.clear(),
                 }
-                MemArgs::Mem2Args(arg1, arg2) => {
-                    if let MemArg::Reg(regnum, _) = arg1 {
-                        if *regnum == 4 {
-                            if let MemArg::Imm(_, _, offset) = arg2 {
-                                self.stack.update(*offset, value, memsize.to_u32() / 8)
-                            }
-                        }
-                    }
-                }
-                _ => (),
-            },
+            }
+            Value::Mem(_, _) => (),
             Value::Reg(regnum, s2) => {
-                if let ValSize::SizeOther = s2 {
+                if *s2 == ValSize::SizeOther || s2.is_vector() {
                 } else {
                     self.regs.set(regnum, s2, value)
                 }
@@ -161,27 +153,15 @@ impl<T: Lattice + Clone> VarState for VariableState<T> {
 
     fn get(&mut self, index: &Value) -> Option<T> {
         match index {
-            Value::Mem(memsize, memargs) => match memargs {
-                MemArgs::Mem1Arg(arg) => {
-                    if let MemArg::Reg(regnum, _) = arg {
-                        if *regnum == 4 {
-                            return Some(self.stack.get(0, memsize.to_u32() / 8));
-                        }
+            Value::Mem(memsize, memargs) if is_stack_access(index) => {
+                match extract_stack_offset(memargs) {
+                    Some(offset) if *memsize != ValSize::SizeOther => {
+                        Some(self.stack.get(offset, memsize.to_u32() / 8))
                     }
-                    None
+                    _ => None,
                 }
-                MemArgs::Mem2Args(arg1, arg2) => {
-                    if let MemArg::Reg(regnum, _) = arg1 {
-                        if *regnum == 4 {
-                            if let MemArg::Imm(_, _, offset) = arg2 {
-                                return Some(self.stack.get(*offset, memsize.to_u32() / 8));
-                            }
-                        }
-                    }
-                    None
-                }
-                _ => None,
-            },
+            }
+            Value::Mem(_, _) => None,
             Value::Reg(regnum, s2) => Some(self.regs.get(regnum, s2)),
             Value::Imm(_, _, _) => None,
             Value::RIPConst => None,
@@ -198,16 +178,16 @@ impl<T: Lattice + Clone> VarState for VariableState<T> {
 
     fn adjust_stack_offset(&mut self, opcode: &Binopcode, dst: &Value, src1: &Value, src2: &Value) {
         if is_rsp(dst) {
-            if is_rsp(src1) {
-                let adjustment = get_imm_offset(src2);
-                //println!("opcode = {:?} {:?} = {:?} {:?} {:?}", opcode, dst, src1, src2, adjustment);
-                match opcode {
-                    Binopcode::Add => self.stack.update_stack_offset(adjustment),
-                    Binopcode::Sub => self.stack.update_stack_offset(-adjustment),
-                    _ => panic!("Illegal RSP write"),
+            match (opcode, is_rsp(src1), src2) {
+                (Binopcode::Add, true, Value::Imm(_, _, adjustment)) => {
+                    self.stack.update_stack_offset(*adjustment)
+                }
+                (Binopcode::Sub, true, Value::Imm(_, _, adjustment)) => {
+                    self.stack.update_stack_offset(-adjustment)
                 }
-            } else {
-                panic!("Illegal RSP write")
+                // rsp moved by an unknown amount, so no slot is where it was.
+                // The stack checker rejects the function.
+                _ => self.stack.map.clear(),
             }
         }
     }
@@ -25,7 +25,7 @@ pub struct X86RegsLattice<T: Lattice + Clone> {
 
 impl<T: Lattice + Clone> X86RegsLattice<T> {
     pub fn get(&self, index: &u8, size: &ValSize) -> T {
-        if let ValSize::SizeOther = size {
+        if *size == ValSize::SizeOther || size.is_vector() {
             return Default::default();
         }
         match index {
@@ -51,7 +51,7 @@ impl<T: Lattice + Clone> X86RegsLattice<T> {
     }
 
     pub fn set(&mut self, index: &u8, size: &ValSize, value: T) -> () {
-        if let ValSize::SizeOther = size {
+        if *size == ValSize::SizeOther || size.is_vector() {
             return;
         }
         match index {
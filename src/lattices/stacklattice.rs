@@ -33,8 +33,14 @@ impl<T: Lattice + Clone> StackLattice<T> {
         if (offset & 3) != 0 {
             panic!("Unsafe: Attempt to store value on the stack on not 4-byte aligned address.");
         }
+        //a vector store is not tracked, but forgets every slot it overlaps
         if size > 8 {
-            panic!("Store too large!");
+            let start = self.offset + offset;
+            let end = start + size as i64;
+            self.map.retain(|slot_offset, slot| {
+                *slot_offset + slot.size as i64 <= start || *slot_offset >= end
+            });
+            return;
         }
         //remove overlapping entries
         //if write is size 8: remove next slot (offset + 4) if one exists
@@ -64,8 +70,9 @@ impl<T: Lattice + Clone> StackLattice<T> {
     }
 
     pub fn get(&self, offset: i64, size: u32) -> T {
+        //only 4 and 8 byte slots are tracked
         if !(size == 4 || size == 8) {
-            panic!("Load wrong size! size = {:?}", size);
+            return Default::default();
         }
 
         match self.map.get(&(self.offset + offset)) {
@@ -253,3 +260,19 @@ fn stack_lattice_test_overlapping_entries() {
     print!("{:?} {:?}", x1, x2);
     assert_eq!(x1 == x2, true);
 }
+
+#[test]
+fn stack_lattice_test_vector_store() {
+    use crate::lattices::BooleanLattice;
+    let mut x1: StackLattice<BooleanLattice> = Default::default();
+    let y = BooleanLattice { v: true };
+
+    //a 16 byte store at 8 overwrites the slots at 4 and 12, but not the one at 24
+    x1.update(4, y.clone(), 8);
+    x1.update(12, y.clone(), 4);
+    x1.update(24, y.clone(), 4);
+    x1.update(8, Default::default(), 16);
+    assert_eq!(x1.get(4, 8), Default::default());
+    assert_eq!(x1.get(12, 4), Default::default());
+    assert_eq!(x1.get(24, 4), y);
+}
@@ -1,26 +1,52 @@
-//! Library entry point for stack and heap validation, given a single
+//! Library entry point for stack, heap and call validation, given a single
 //! function's machine code and basic-block offsets.
 
 #![allow(dead_code, unused_imports, unused_variables)]
 
+use crate::analyses::call_analyzer::CallAnalyzer;
 use crate::analyses::heap_analyzer::HeapAnalyzer;
+use crate::analyses::reaching_defs::{analyze_reaching_defs, ReachingDefnAnalyzer};
 use crate::analyses::run_worklist;
+use crate::analyses::stack_analyzer::StackAnalyzer;
+use crate::checkers::call_checker::check_calls;
 use crate::checkers::heap_checker::check_heap;
+use crate::checkers::jump_resolver::check_jump_tables;
+use crate::checkers::stack_checker::check_stack;
+use crate::profile::{GlobalsLayout, TableLayout, VMCtxLayout};
+use crate::utils::ir_utils::{has_indirect_calls, has_indirect_jumps};
 use crate::utils::lifter::{lift_cfg, IRMap, MemArg, MemArgs};
+use crate::utils::relocs::write_reloc;
 use crate::utils::utils::LucetMetadata;
 use petgraph::graphmap::GraphMap;
-use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use yaxpeax_core::analyses::control_flow::{get_cfg, VW_Block, VW_CFG};
 use yaxpeax_core::memory::repr::process::{ModuleData, ModuleInfo, Segment};
 
 pub mod analyses;
 pub mod checkers;
 pub mod lattices;
+pub mod profile;
+pub mod report;
+pub mod sarif;
 pub mod utils;
+pub mod verify;
+
+pub use verify::{verify_module, VerifyConfig};
 
 #[derive(Clone, Copy, Debug)]
 pub enum ValidationError {
     HeapUnsafe,
+    StackUnsafe,
+    CallUnsafe,
+    /// An indirect jump is not a bounds-checked jump-table lookup whose
+    /// targets are the CFG edges given for it.
+    JumpUnsafe,
+    /// A relocation does not fit in the function's code, or refers to a
+    /// symbol or function the config has no address for.
+    InvalidReloc,
+    /// The analysis panicked, so the function is not known to be safe.
+    InternalError,
 }
 impl std::fmt::Display for ValidationError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -33,7 +59,7 @@ impl std::error::Error for ValidationError {}
 /// check to be parameterized to work with different VMs -- first
 /// Lucet, eventually Wasmtime, perhaps others -- that have slightly
 /// different VM-context data structure layouts.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum HeapStrategy {
     /// The first argument to functions is a hidden argument that is
     /// the heap base. Accesses to the heap are computed relative to
@@ -52,13 +78,129 @@ pub enum HeapStrategy {
     ///
     /// This corresponds to Wasmtime's design.
     VMCtxFirstArgWithGuards { vmctx_heap_base_ptr_offset: usize },
+
+    /// Like `VMCtxFirstArgWithGuards`, but with no guard region: each
+    /// heap access must be dominated by an explicit check of its index
+    /// against the heap bound, which is also loaded from the vmctx
+    /// struct. The check must cover every byte accessed: either
+    /// `cmp idx, bound; jae trap` for a one-byte access, or the end of
+    /// the access is computed and compared (`lea tmp, [idx + offset +
+    /// width]; cmp tmp, bound; ja trap`).
+    ///
+    /// Indexes are not assumed to fit in 32 bits, so this is also the
+    /// strategy for memory64 heaps, where the bound is the dynamic
+    /// memory length. A 64-bit index plus the access's offset and width
+    /// can wrap around, so it must be computed with an add whose carry
+    /// is checked (`mov tmp, idx; add tmp, offset + width; jb trap`).
+    VMCtxFirstArgWithBoundsChecks {
+        vmctx_heap_base_ptr_offset: usize,
+        vmctx_heap_bound_offset: usize,
+    },
+
+    /// A vmctx struct pointer as above, with several linear memories
+    /// (the Wasm multi-memory proposal). Memory N is described by
+    /// `memories[N]`; an access must pair the base of memory N with an
+    /// index bounded for memory N.
+    VMCtxFirstArgMultiMemory { memories: Vec<VMCtxMemory> },
+}
+
+/// How functions make sure their stack frames don't overflow the stack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StackStrategy {
+    /// Frames larger than a page are probed by calling the stack-probe
+    /// function, and a guard page is assumed below the stack.
+    ///
+    /// This corresponds to Lucet's design.
+    ProbeStack,
+
+    /// The prologue loads a stack limit from the vmctx struct (the first
+    /// argument) and traps if `rsp` is below the limit plus the frame
+    /// size (`cmp rsp, limit; jb trap`). The check must be at the end of
+    /// the function's entry block.
+    ///
+    /// This corresponds to Wasmtime's design.
+    VMCtxStackLimit { vmctx_stack_limit_offset: usize },
+}
+
+/// Where one linear memory's fields are in the vmctx struct.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VMCtxMemory {
+    pub heap_base_ptr_offset: usize,
+    /// The offset of the heap bound, if accesses are bounds-checked
+    /// rather than protected by guard regions.
+    pub heap_bound_offset: Option<usize>,
+}
+
+/// A runtime symbol that compiled code refers to through a relocation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RuntimeSymbol {
+    GuestTable0,
+    LucetTables,
+    ProbeStack,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelocKind {
+    /// An 8-byte absolute address (Cranelift's `Abs8`).
+    Abs8,
+    /// A 4-byte absolute address (Cranelift's `Abs4`).
+    Abs4,
+    /// A 4-byte offset from the relocation site, as in `call rel32`
+    /// (Cranelift's `X86PCRel4` and `X86CallPCRel4`).
+    PCRel4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelocTarget {
+    Runtime(RuntimeSymbol),
+    /// Another Wasm function, by index. Direct calls to it are allowed.
+    Function(u32),
+}
+
+/// A relocation the compiler left in a function's code, to be filled in
+/// when the code is placed in memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reloc {
+    pub offset: usize,
+    pub kind: RelocKind,
+    pub target: RelocTarget,
+    pub addend: i64,
+}
+
+/// What `validate_function` needs to know about the runtime.
+#[derive(Clone, Debug)]
+pub struct ValidationConfig {
+    pub heap_strategy: HeapStrategy,
+    pub stack_strategy: StackStrategy,
+    /// Addresses of the runtime symbols relocations may refer to.
+    pub symbol_addrs: HashMap<RuntimeSymbol, u64>,
+    /// Addresses of the other functions relocations may refer to, by index.
+    pub func_addrs: HashMap<u32, u64>,
+    pub tables: TableLayout,
+    pub globals: GlobalsLayout,
+    pub vmctx: VMCtxLayout,
+}
+
+impl ValidationConfig {
+    pub fn new(heap_strategy: HeapStrategy, stack_strategy: StackStrategy) -> Self {
+        ValidationConfig {
+            heap_strategy,
+            stack_strategy,
+            symbol_addrs: HashMap::new(),
+            func_addrs: HashMap::new(),
+            tables: Default::default(),
+            globals: Default::default(),
+            vmctx: Default::default(),
+        }
+    }
 }
 
 fn func_body_and_bbs_to_cfg(
     code: &[u8],
     basic_blocks: &[usize],
     cfg_edges: &[(usize, usize)],
-) -> (VW_CFG, IRMap, LucetMetadata) {
+    metadata: &LucetMetadata,
+) -> (VW_CFG, IRMap, ModuleData) {
     // We build the VW_CFG manually; we skip the CFG-recovery
     // algorithm that has to analyze the machine code and compute
     // reaching-defs in a fixpoint loop.
@@ -125,21 +267,39 @@ fn func_body_and_bbs_to_cfg(
         name: "function.o".to_owned(),
         module_info,
     };
-    let lucet = LucetMetadata {
-        guest_table_0: 0x123456789abcdef0,
-        lucet_tables: 0x123456789abcdef0,
-        lucet_probestack: 0x123456789abcdef0,
-    };
+    let irmap = lift_cfg(&data, &cfg, metadata);
 
-    let irmap = lift_cfg(&data, &cfg, &lucet);
-
-    (cfg, irmap, lucet)
+    (cfg, irmap, data)
 
     // TODO: regalloc checker from Lucet too.
     // TODO: audit opcodes. Fallback to just clear dest(s) on unknown?
-    // TODO: how hard would this be to adapt to Wasmtime? Extra level of indirection:
-    //       heap-base loaded from vmctx (rdi) instead. Take a mode argument?
-    //       "Heap-access style"
+}
+
+// Jump tables are only resolved if there are indirect jumps, since the
+// reaching-defs pass this needs is slow.
+fn check_function_jumps(
+    program: &ModuleData,
+    cfg: &VW_CFG,
+    irmap: &IRMap,
+    metadata: &LucetMetadata,
+) -> bool {
+    if !has_indirect_jumps(irmap) {
+        return true;
+    }
+    let violations = check_jump_tables(program, cfg, irmap, metadata, "function");
+    for violation in &violations {
+        log::debug!("{}", violation);
+    }
+    violations.is_empty()
+}
+
+// Runs a check, treating a panic as an error rather than unwinding into
+// the caller.
+fn catch_panics(
+    check: impl FnOnce() -> Result<(), ValidationError>,
+) -> Result<(), ValidationError> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(check))
+        .unwrap_or(Err(ValidationError::InternalError))
 }
 
 pub fn validate_heap(
@@ -147,25 +307,23 @@ pub fn validate_heap(
     basic_blocks: &[usize],
     cfg_edges: &[(usize, usize)],
     heap_strategy: HeapStrategy,
+) -> Result<(), ValidationError> {
+    catch_panics(|| validate_heap_uncaught(code, basic_blocks, cfg_edges, heap_strategy))
+}
+
+fn validate_heap_uncaught(
+    code: &[u8],
+    basic_blocks: &[usize],
+    cfg_edges: &[(usize, usize)],
+    heap_strategy: HeapStrategy,
 ) -> Result<(), ValidationError> {
     log::debug!(
         "validate_heap: basic_blocks = {:?}, edges = {:?}",
         basic_blocks,
         cfg_edges
     );
-    // For now, we don't support Wasmtime-style heap accesses.
-    // TODO: implement these:
-    // - Add a lattice value: VMCtxPtr
-    // - Add a rule: load from [VMCtxPtr + heap_base_ptr_offset] -> HeapBase
-    match heap_strategy {
-        HeapStrategy::HeapPtrFirstArgWithGuards => {}
-        _ => {
-            log::debug!("Unknown heap strategy: {:?}", heap_strategy);
-            return Err(ValidationError::HeapUnsafe);
-        }
-    }
-
-    let (cfg, irmap, metadata) = func_body_and_bbs_to_cfg(code, basic_blocks, cfg_edges);
+    let metadata = LucetMetadata::default();
+    let (cfg, irmap, program) = func_body_and_bbs_to_cfg(code, basic_blocks, cfg_edges, &metadata);
 
     // This entry point is designed to allow checking of a single
     // function body, just after it has been generated in memory,
@@ -175,7 +333,8 @@ pub fn validate_heap(
     // Without symbols/relocs, we don't know which calls are to
     // `lucet_probestack()`, so we can't do a stack-use soundness
     // check, and we don't know which globals are `lucet_tables` and
-    // `guest_table_0`, so we can't check instance function calls. We
+    // `guest_table_0`, so we can't check instance function calls
+    // (`validate_function` takes the relocations and does). We
     // also can't really do the full CFG recovery analysis and CFI
     // checks because it's very expensive (the reaching-defs analysis
     // has not been optimized) and requires knowing other function
@@ -194,14 +353,474 @@ pub fn validate_heap(
     // heap access is the most exposed -- it's just ordinary CLIF IR
     // that goes through the compilation pipeline with opt passes like
     // all other code. It's also the fastest and simplest to check.
+    //
+    // The one exception to trusting the CFG is `br_table`: the edges out
+    // of an indirect jump must be exactly its jump table's targets, or the
+    // heap analysis would not see where the jump can go.
+    if !check_function_jumps(&program, &cfg, &irmap, &metadata) {
+        return Err(ValidationError::JumpUnsafe);
+    }
     let heap_analyzer = HeapAnalyzer {
         metadata: metadata.clone(),
+        strategy: heap_strategy,
     };
     let heap_result = run_worklist(&cfg, &irmap, &heap_analyzer);
-    let heap_safe = check_heap(heap_result, &irmap, &heap_analyzer);
-    if !heap_safe {
+    let heap_violations = check_heap(heap_result, &irmap, &heap_analyzer, "function");
+    if !heap_violations.is_empty() {
+        for violation in &heap_violations {
+            log::debug!("{}", violation);
+        }
         return Err(ValidationError::HeapUnsafe);
     }
 
     Ok(())
 }
+
+/// Like `validate_heap`, but also checks stack and call safety, using the
+/// relocations the compiler emitted to find the calls to the stack probe
+/// and the uses of the call tables. The relocations are applied as if
+/// `code` were at address 0, with their targets at the addresses in
+/// `config`.
+pub fn validate_function(
+    code: &[u8],
+    basic_blocks: &[usize],
+    cfg_edges: &[(usize, usize)],
+    relocs: &[Reloc],
+    config: &ValidationConfig,
+) -> Result<(), ValidationError> {
+    catch_panics(|| validate_function_uncaught(code, basic_blocks, cfg_edges, relocs, config))
+}
+
+fn validate_function_uncaught(
+    code: &[u8],
+    basic_blocks: &[usize],
+    cfg_edges: &[(usize, usize)],
+    relocs: &[Reloc],
+    config: &ValidationConfig,
+) -> Result<(), ValidationError> {
+    let mut code = code.to_vec();
+    for reloc in relocs {
+        let target = match reloc.target {
+            RelocTarget::Runtime(symbol) => config.symbol_addrs.get(&symbol),
+            RelocTarget::Function(idx) => config.func_addrs.get(&idx),
+        }
+        .ok_or(ValidationError::InvalidReloc)?;
+        let target = *target as i64;
+        let (size, value) = match reloc.kind {
+            RelocKind::Abs8 => (8, target + reloc.addend),
+            RelocKind::Abs4 => (4, target + reloc.addend),
+            RelocKind::PCRel4 => (4, target + reloc.addend - reloc.offset as i64),
+        };
+        write_reloc(&mut code, reloc.offset, size, value)
+            .map_err(|_| ValidationError::InvalidReloc)?;
+    }
+    let metadata = LucetMetadata {
+        guest_table_0: config
+            .symbol_addrs
+            .get(&RuntimeSymbol::GuestTable0)
+            .cloned(),
+        lucet_tables: config
+            .symbol_addrs
+            .get(&RuntimeSymbol::LucetTables)
+            .cloned(),
+        lucet_probestack: config.symbol_addrs.get(&RuntimeSymbol::ProbeStack).cloned(),
+        tables: config.tables,
+        globals: config.globals,
+        vmctx: config.vmctx,
+    };
+    let (cfg, irmap, program) = func_body_and_bbs_to_cfg(&code, basic_blocks, cfg_edges, &metadata);
+    if !check_function_jumps(&program, &cfg, &irmap, &metadata) {
+        return Err(ValidationError::JumpUnsafe);
+    }
+
+    let stack_analyzer = StackAnalyzer {
+        strategy: config.stack_strategy,
+        entrypoint: cfg.entrypoint,
+    };
+    let stack_result = run_worklist(&cfg, &irmap, &stack_analyzer);
+    let stack_violations = check_stack(stack_result, &irmap, &stack_analyzer, "function");
+
+    let heap_analyzer = HeapAnalyzer {
+        metadata: metadata.clone(),
+        strategy: config.heap_strategy.clone(),
+    };
+    let heap_result = run_worklist(&cfg, &irmap, &heap_analyzer);
+    let heap_violations = check_heap(heap_result, &irmap, &heap_analyzer, "function");
+
+    let mut call_violations = vec![];
+    if has_indirect_calls(&irmap) {
+        // The function itself is at 0.
+        let mut funcs: Vec<u64> = config.func_addrs.values().cloned().collect();
+        funcs.push(0);
+        let call_analyzer = CallAnalyzer {
+            metadata: metadata.clone(),
+            reaching_defs: analyze_reaching_defs(&cfg, &irmap, metadata.clone()),
+            reaching_analyzer: ReachingDefnAnalyzer {
+                cfg: cfg.clone(),
+                irmap: irmap.clone(),
+            },
+            funcs: funcs.clone(),
+        };
+        let call_result = run_worklist(&cfg, &irmap, &call_analyzer);
+        call_violations = check_calls(
+            call_result,
+            &irmap,
+            &call_analyzer,
+            &funcs,
+            &(0, 0),
+            "function",
+        );
+    }
+
+    for violation in stack_violations
+        .iter()
+        .chain(&heap_violations)
+        .chain(&call_violations)
+    {
+        log::debug!("{}", violation);
+    }
+    if !stack_violations.is_empty() {
+        Err(ValidationError::StackUnsafe)
+    } else if !heap_violations.is_empty() {
+        Err(ValidationError::HeapUnsafe)
+    } else if !call_violations.is_empty() {
+        Err(ValidationError::CallUnsafe)
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn validate_heap_vmctx_test() {
+    let strategy = HeapStrategy::VMCtxFirstArgWithGuards {
+        vmctx_heap_base_ptr_offset: 0x40,
+    };
+    // mov rax, [rdi + 0x40]; mov ecx, esi; mov edx, [rax + rcx]; ret
+    let code = [0x48, 0x8b, 0x47, 0x40, 0x89, 0xf1, 0x8b, 0x14, 0x08, 0xc3];
+    assert!(validate_heap(&code, &[0], &[], strategy.clone()).is_ok());
+    // mov ecx, esi; mov edx, [rdi + rcx]; ret -- vmctx used as the heap base
+    let code = [0x89, 0xf1, 0x8b, 0x14, 0x0f, 0xc3];
+    assert!(validate_heap(&code, &[0], &[], strategy.clone()).is_err());
+    // mov rax, [rdi + 0x48]; mov ecx, esi; mov edx, [rax + rcx]; ret -- wrong vmctx field
+    let code = [0x48, 0x8b, 0x47, 0x48, 0x89, 0xf1, 0x8b, 0x14, 0x08, 0xc3];
+    assert!(validate_heap(&code, &[0], &[], strategy.clone()).is_err());
+}
+
+#[test]
+fn validate_heap_scaled_index_test() {
+    let strategy = HeapStrategy::HeapPtrFirstArgWithGuards;
+    // lea rax, [rcx * 8 + 0x10]; ret
+    let code = [0x48, 0x8d, 0x04, 0xcd, 0x10, 0x00, 0x00, 0x00, 0xc3];
+    assert!(validate_heap(&code, &[0], &[], strategy.clone()).is_ok());
+    // mov ecx, esi; mov eax, [rdi + rcx * 4]; ret -- may reach past the guard region
+    let code = [0x89, 0xf1, 0x8b, 0x04, 0x8f, 0xc3];
+    assert!(validate_heap(&code, &[0], &[], strategy.clone()).is_err());
+}
+
+#[test]
+fn validate_heap_rep_stos_test() {
+    let strategy = HeapStrategy::HeapPtrFirstArgWithGuards;
+    // mov eax, esi; add rdi, rax; mov ecx, edx; rep stosb; ret
+    let code = [0x89, 0xf0, 0x48, 0x01, 0xc7, 0x89, 0xd1, 0xf3, 0xaa, 0xc3];
+    assert!(validate_heap(&code, &[0], &[], strategy.clone()).is_ok());
+    // as above with rep stosq -- 8 * a 32-bit count can reach past the guard region
+    let code = [
+        0x89, 0xf0, 0x48, 0x01, 0xc7, 0x89, 0xd1, 0xf3, 0x48, 0xab, 0xc3,
+    ];
+    assert!(validate_heap(&code, &[0], &[], strategy.clone()).is_err());
+    // mov eax, esi; add rdi, rax; mov ecx, edx; shr rcx, 3; rep stosq; ret
+    let code = [
+        0x89, 0xf0, 0x48, 0x01, 0xc7, 0x89, 0xd1, 0x48, 0xc1, 0xe9, 0x03, 0xf3, 0x48, 0xab, 0xc3,
+    ];
+    assert!(validate_heap(&code, &[0], &[], strategy.clone()).is_ok());
+    // mov eax, esi; mov rsi, rdi; add rsi, rax; mov eax, edx; add rdi, rax;
+    // mov ecx, ecx; shr rcx, 3; rep movsq; ret
+    let code = [
+        0x89, 0xf0, 0x48, 0x89, 0xfe, 0x48, 0x01, 0xc6, 0x89, 0xd0, 0x48, 0x01, 0xc7, 0x89, 0xc9,
+        0x48, 0xc1, 0xe9, 0x03, 0xf3, 0x48, 0xa5, 0xc3,
+    ];
+    assert!(validate_heap(&code, &[0], &[], strategy.clone()).is_ok());
+    // std; ...; rep stosb; cld; ret -- stores downwards from rdi
+    let code = [
+        0xfd, 0x89, 0xf0, 0x48, 0x01, 0xc7, 0x89, 0xd1, 0xf3, 0xaa, 0xfc, 0xc3,
+    ];
+    assert!(validate_heap(&code, &[0], &[], strategy.clone()).is_err());
+}
+
+#[test]
+fn validate_heap_bounds_checks_test() {
+    let strategy = HeapStrategy::VMCtxFirstArgWithBoundsChecks {
+        vmctx_heap_base_ptr_offset: 0x40,
+        vmctx_heap_bound_offset: 0x48,
+    };
+    // mov esi, esi; mov rax, [rdi + 0x48]; lea rdx, [rsi + 4]; cmp rdx, rax; ja trap;
+    // mov rcx, [rdi + 0x40]; mov eax, [rcx + rsi]; ret; trap: ud2
+    let code = [
+        0x89, 0xf6, 0x48, 0x8b, 0x47, 0x48, 0x48, 0x8d, 0x56, 0x04, 0x48, 0x39, 0xc2, 0x77, 0x08,
+        0x48, 0x8b, 0x4f, 0x40, 0x8b, 0x04, 0x31, 0xc3, 0x0f, 0x0b,
+    ];
+    let blocks = [0, 15, 23];
+    let edges = [(0, 15), (0, 23)];
+    assert!(validate_heap(&code, &blocks, &edges, strategy.clone()).is_ok());
+    // as above with `lea rdx, [rsi + 2]`: the check doesn't cover the 4 byte access
+    let mut code_narrow = code;
+    code_narrow[9] = 0x02;
+    assert!(validate_heap(&code_narrow, &blocks, &edges, strategy.clone()).is_err());
+    // as above with `jbe`: the access is on the out-of-bounds edge
+    let mut code_jbe = code;
+    code_jbe[13] = 0x76;
+    assert!(validate_heap(&code_jbe, &blocks, &edges, strategy.clone()).is_err());
+
+    // mov rax, [rdi + 0x48]; cmp rsi, rax; jae trap;
+    // mov rcx, [rdi + 0x40]; movzx eax, byte [rcx + rsi]; ret; trap: ud2
+    let code = [
+        0x48, 0x8b, 0x47, 0x48, 0x48, 0x39, 0xc6, 0x73, 0x09, 0x48, 0x8b, 0x4f, 0x40, 0x0f, 0xb6,
+        0x04, 0x31, 0xc3, 0x0f, 0x0b,
+    ];
+    let blocks = [0, 9, 18];
+    let edges = [(0, 9), (0, 18)];
+    assert!(validate_heap(&code, &blocks, &edges, strategy.clone()).is_ok());
+    // as above with `jb`: the access is on the out-of-bounds edge
+    let mut code_jb = code;
+    code_jb[7] = 0x72;
+    assert!(validate_heap(&code_jb, &blocks, &edges, strategy.clone()).is_err());
+
+    // mov rax, [rdi + 0x48]; cmp rsi, rax; jae trap;
+    // mov rcx, [rdi + 0x40]; mov eax, [rcx + rsi]; ret; trap: ud2
+    // -- rsi < bound, but the last 3 bytes may be out of bounds
+    let code = [
+        0x48, 0x8b, 0x47, 0x48, 0x48, 0x39, 0xc6, 0x73, 0x08, 0x48, 0x8b, 0x4f, 0x40, 0x8b, 0x04,
+        0x31, 0xc3, 0x0f, 0x0b,
+    ];
+    assert!(validate_heap(&code, &[0, 9, 17], &[(0, 9), (0, 17)], strategy.clone()).is_err());
+    // mov rcx, [rdi + 0x40]; mov esi, esi; mov eax, [rcx + rsi]; ret -- unchecked 32-bit index
+    let code = [0x48, 0x8b, 0x4f, 0x40, 0x89, 0xf6, 0x8b, 0x04, 0x31, 0xc3];
+    assert!(validate_heap(&code, &[0], &[], strategy.clone()).is_err());
+}
+
+#[test]
+fn validate_heap_memory64_test() {
+    let strategy = HeapStrategy::VMCtxFirstArgWithBoundsChecks {
+        vmctx_heap_base_ptr_offset: 0x40,
+        vmctx_heap_bound_offset: 0x48,
+    };
+    // mov rax, [rdi + 0x48]; mov rdx, rsi; add rdx, 12; jb trap; cmp rdx, rax; ja trap;
+    // mov rcx, [rdi + 0x40]; mov eax, [rcx + rsi + 8]; ret; trap: ud2
+    let code = [
+        0x48, 0x8b, 0x47, 0x48, 0x48, 0x89, 0xf2, 0x48, 0x83, 0xc2, 0x0c, 0x72, 0x0e, 0x48, 0x39,
+        0xc2, 0x77, 0x09, 0x48, 0x8b, 0x4f, 0x40, 0x8b, 0x44, 0x31, 0x08, 0xc3, 0x0f, 0x0b,
+    ];
+    let blocks = [0, 13, 18, 27];
+    let edges = [(0, 13), (0, 27), (13, 18), (13, 27)];
+    assert!(validate_heap(&code, &blocks, &edges, strategy.clone()).is_ok());
+    // as above with `add rdx, 11`: the last byte may be out of bounds
+    let mut code_narrow = code;
+    code_narrow[10] = 0x0b;
+    assert!(validate_heap(&code_narrow, &blocks, &edges, strategy.clone()).is_err());
+    // as above with `jnb`: the add may have wrapped around
+    let mut code_wrap = code;
+    code_wrap[11] = 0x73;
+    assert!(validate_heap(&code_wrap, &blocks, &edges, strategy.clone()).is_err());
+}
+
+#[test]
+fn validate_heap_multi_memory_test() {
+    let strategy = HeapStrategy::VMCtxFirstArgMultiMemory {
+        memories: vec![
+            VMCtxMemory {
+                heap_base_ptr_offset: 0x40,
+                heap_bound_offset: Some(0x48),
+            },
+            VMCtxMemory {
+                heap_base_ptr_offset: 0x50,
+                heap_bound_offset: Some(0x58),
+            },
+        ],
+    };
+    // mov esi, esi; mov rax, [rdi + 0x48]; lea rdx, [rsi + 4]; cmp rdx, rax; ja trap;
+    // mov rcx, [rdi + 0x40]; mov eax, [rcx + rsi]; ret; trap: ud2
+    let code = [
+        0x89, 0xf6, 0x48, 0x8b, 0x47, 0x48, 0x48, 0x8d, 0x56, 0x04, 0x48, 0x39, 0xc2, 0x77, 0x08,
+        0x48, 0x8b, 0x4f, 0x40, 0x8b, 0x04, 0x31, 0xc3, 0x0f, 0x0b,
+    ];
+    let blocks = [0, 15, 23];
+    let edges = [(0, 15), (0, 23)];
+    assert!(validate_heap(&code, &blocks, &edges, strategy.clone()).is_ok());
+    // checked against memory 0's bound, but accessing memory 1
+    let mut code_mixed = code;
+    code_mixed[18] = 0x50;
+    assert!(validate_heap(&code_mixed, &blocks, &edges, strategy.clone()).is_err());
+    // checked against memory 1's bound, accessing memory 1
+    code_mixed[5] = 0x58;
+    assert!(validate_heap(&code_mixed, &blocks, &edges, strategy.clone()).is_ok());
+}
+
+#[cfg(test)]
+fn stack_limit_check(vmctx_stack_limit_offset: usize) -> Vec<checkers::Violation> {
+    use crate::analyses::stack_analyzer::StackAnalyzer;
+    use crate::checkers::stack_checker::check_stack;
+    // push rbp; mov rbp, rsp; mov r10, [rdi + 0x30]; add r10, 0x2000;
+    // cmp rsp, r10; jb trap;
+    // sub rsp, 0x2000; mov [rsp], rdi; add rsp, 0x2000; pop rbp; ret; trap: ud2
+    let code = [
+        0x55, 0x48, 0x89, 0xe5, 0x4c, 0x8b, 0x57, 0x30, 0x49, 0x81, 0xc2, 0x00, 0x20, 0x00, 0x00,
+        0x4c, 0x39, 0xd4, 0x72, 0x14, 0x48, 0x81, 0xec, 0x00, 0x20, 0x00, 0x00, 0x48, 0x89, 0x3c,
+        0x24, 0x48, 0x81, 0xc4, 0x00, 0x20, 0x00, 0x00, 0x5d, 0xc3, 0x0f, 0x0b,
+    ];
+    let (cfg, irmap, _) = func_body_and_bbs_to_cfg(
+        &code,
+        &[0, 20, 40],
+        &[(0, 20), (0, 40)],
+        &LucetMetadata::default(),
+    );
+    let stack_analyzer = StackAnalyzer {
+        strategy: StackStrategy::VMCtxStackLimit {
+            vmctx_stack_limit_offset,
+        },
+        entrypoint: cfg.entrypoint,
+    };
+    let stack_result = run_worklist(&cfg, &irmap, &stack_analyzer);
+    check_stack(stack_result, &irmap, &stack_analyzer, "function")
+}
+
+#[test]
+fn stack_limit_check_test() {
+    assert!(stack_limit_check(0x30).is_empty());
+}
+
+#[test]
+fn stack_limit_wrong_field_test() {
+    // an 8K frame with no check of the right limit
+    let violations = stack_limit_check(0x38);
+    assert!(violations
+        .iter()
+        .any(|v| v.kind == checkers::ViolationKind::StackFrameNotProbed));
+}
+
+#[test]
+fn validate_function_probestack_test() {
+    let mut config = ValidationConfig::new(
+        HeapStrategy::HeapPtrFirstArgWithGuards,
+        StackStrategy::ProbeStack,
+    );
+    // push rbp; mov rbp, rsp; mov eax, 0x2000; call lucet_probestack;
+    // sub rsp, rax; add rsp, 0x2000; pop rbp; ret
+    let code = [
+        0x55, 0x48, 0x89, 0xe5, 0xb8, 0x00, 0x20, 0x00, 0x00, 0xe8, 0x00, 0x00, 0x00, 0x00, 0x48,
+        0x29, 0xc4, 0x48, 0x81, 0xc4, 0x00, 0x20, 0x00, 0x00, 0x5d, 0xc3,
+    ];
+    let relocs = [Reloc {
+        offset: 10,
+        kind: RelocKind::PCRel4,
+        target: RelocTarget::Runtime(RuntimeSymbol::ProbeStack),
+        addend: -4,
+    }];
+    // the probe's address isn't known
+    let result = validate_function(&code, &[0], &[], &relocs, &config);
+    assert!(matches!(result, Err(ValidationError::InvalidReloc)));
+    config
+        .symbol_addrs
+        .insert(RuntimeSymbol::ProbeStack, 0x10000);
+    assert!(validate_function(&code, &[0], &[], &relocs, &config).is_ok());
+
+    // push rbp; mov rbp, rsp; sub rsp, 0x3000; add rsp, 0x3000; pop rbp; ret
+    // -- a 12K frame with no call to the probe
+    let code = [
+        0x55, 0x48, 0x89, 0xe5, 0x48, 0x81, 0xec, 0x00, 0x30, 0x00, 0x00, 0x48, 0x81, 0xc4, 0x00,
+        0x30, 0x00, 0x00, 0x5d, 0xc3,
+    ];
+    let result = validate_function(&code, &[0], &[], &[], &config);
+    assert!(matches!(result, Err(ValidationError::StackUnsafe)));
+}
+
+#[test]
+fn validate_function_call_table_test() {
+    let mut config = ValidationConfig::new(
+        HeapStrategy::HeapPtrFirstArgWithGuards,
+        StackStrategy::ProbeStack,
+    );
+    config
+        .symbol_addrs
+        .insert(RuntimeSymbol::LucetTables, 0x10000);
+    config
+        .symbol_addrs
+        .insert(RuntimeSymbol::GuestTable0, 0x20000);
+    // movabs rax, lucet_tables; mov rcx, [rax + 8]; cmp rsi, rcx; jae trap;
+    // movabs rdx, guest_table_0; shl rsi, 4; mov rax, [rdx + rsi + 8];
+    // call rax; ret; trap: ud2
+    let code = [
+        0x48, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0x48, 0x8b, 0x48, 0x08, 0x48, 0x39, 0xce, 0x73, 0x16,
+        0x48, 0xba, 0, 0, 0, 0, 0, 0, 0, 0, 0x48, 0xc1, 0xe6, 0x04, 0x48, 0x8b, 0x44, 0x32, 0x08,
+        0xff, 0xd0, 0xc3, 0x0f, 0x0b,
+    ];
+    let reloc = |offset, symbol| Reloc {
+        offset,
+        kind: RelocKind::Abs8,
+        target: RelocTarget::Runtime(symbol),
+        addend: 0,
+    };
+    let relocs = [
+        reloc(2, RuntimeSymbol::LucetTables),
+        reloc(21, RuntimeSymbol::GuestTable0),
+    ];
+    let blocks = [0, 19, 41];
+    let edges = [(0, 19), (0, 41)];
+    assert!(validate_function(&code, &blocks, &edges, &relocs, &config).is_ok());
+    // as above with `jb`: the lookup is on the out-of-bounds edge
+    let mut code_jb = code;
+    code_jb[17] = 0x72;
+    let result = validate_function(&code_jb, &blocks, &edges, &relocs, &config);
+    assert!(matches!(result, Err(ValidationError::CallUnsafe)));
+    // a relocation to a function the config doesn't place
+    let relocs = [
+        reloc(2, RuntimeSymbol::LucetTables),
+        Reloc {
+            target: RelocTarget::Function(3),
+            ..reloc(21, RuntimeSymbol::GuestTable0)
+        },
+    ];
+    let result = validate_function(&code, &blocks, &edges, &relocs, &config);
+    assert!(matches!(result, Err(ValidationError::InvalidReloc)));
+}
+
+#[test]
+fn catch_panics_test() {
+    let result = catch_panics(|| panic!("analysis bug"));
+    assert!(matches!(result, Err(ValidationError::InternalError)));
+}
+
+#[test]
+fn validate_function_invalid_stack_test() {
+    let config = ValidationConfig::new(
+        HeapStrategy::HeapPtrFirstArgWithGuards,
+        StackStrategy::ProbeStack,
+    );
+    // mov esp, eax; ret
+    let result = validate_function(&[0x89, 0xc4, 0xc3], &[0], &[], &[], &config);
+    assert!(matches!(result, Err(ValidationError::StackUnsafe)));
+    // mov rax, [rsp + rcx]; ret
+    let code = [0x48, 0x8b, 0x04, 0x0c, 0xc3];
+    let result = validate_function(&code, &[0], &[], &[], &config);
+    assert!(matches!(result, Err(ValidationError::StackUnsafe)));
+}
+
+#[test]
+fn br_table_edges_test() {
+    // cmp esi, 2; jae default; lea rax, [rip + table]; movsxd rcx, [rax + rsi * 4];
+    // add rax, rcx; jmp rax; table: case0 - table, case1 - table;
+    // case0: ret; case1: ret; default: ret
+    let code = [
+        0x83, 0xfe, 0x02, 0x73, 0x1a, 0x48, 0x8d, 0x05, 0x09, 0x00, 0x00, 0x00, 0x48, 0x63, 0x0c,
+        0xb0, 0x48, 0x01, 0xc8, 0xff, 0xe0, 0x08, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0xc3,
+        0xc3, 0xc3,
+    ];
+    let blocks = [0, 5, 29, 30, 31];
+    let metadata = LucetMetadata::default();
+    let edges = [(0, 5), (0, 31), (5, 29), (5, 30)];
+    let (cfg, irmap, program) = func_body_and_bbs_to_cfg(&code, &blocks, &edges, &metadata);
+    assert!(check_function_jumps(&program, &cfg, &irmap, &metadata));
+    // case1 sent to the default block instead
+    let edges = [(0, 5), (0, 31), (5, 29), (5, 31)];
+    let (cfg, irmap, program) = func_body_and_bbs_to_cfg(&code, &blocks, &edges, &metadata);
+    assert!(!check_function_jumps(&program, &cfg, &irmap, &metadata));
+}
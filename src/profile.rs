@@ -0,0 +1,177 @@
+//! Runtime profiles: the ABI conventions of the runtime that compiled a
+//! module, loaded from a JSON file with `veriwasm --profile`.
+//!
+//! Every field has a default, taken from Lucet, so a profile only needs to
+//! list what its runtime does differently.
+
+use crate::{HeapStrategy, StackStrategy};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuntimeProfile {
+    pub name: String,
+    pub heap: HeapStrategy,
+    pub stack: StackStrategy,
+    pub symbols: SymbolNames,
+    pub tables: TableLayout,
+    pub globals: GlobalsLayout,
+    pub vmctx: VMCtxLayout,
+}
+
+/// Names of the runtime symbols the analyses look up in the module. A
+/// symbol that is `None` is never matched, so code relying on it is reported
+/// as unsafe rather than trusted. A named symbol that is missing from a
+/// linked module is a load error.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SymbolNames {
+    /// Base of the indirect-call table.
+    pub guest_table_0: Option<String>,
+    /// Table descriptors; the table size is at `tables.size_offset`.
+    pub lucet_tables: Option<String>,
+    /// Stack-probe function called before large stack frames are used.
+    pub probestack: Option<String>,
+}
+
+/// Layout of the indirect-call table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TableLayout {
+    /// Offset of the table size from the `lucet_tables` symbol.
+    pub size_offset: i64,
+    /// Log2 of the size of a table entry.
+    pub entry_shift: i64,
+    /// Offset of the function pointer within a table entry.
+    pub fn_ptr_offset: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GlobalsLayout {
+    /// Largest offset from the globals base that is accessed.
+    pub max_offset: i64,
+}
+
+/// The vmctx struct that Wasmtime-style runtimes pass as the first argument.
+/// Code may read it but not write it, since the analyses trust the heap base,
+/// heap bound and stack limit fields they load from it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VMCtxLayout {
+    /// Size of the struct; reads must be within it.
+    pub size: i64,
+}
+
+impl RuntimeProfile {
+    pub fn lucet() -> Self {
+        RuntimeProfile {
+            name: "lucet".to_string(),
+            heap: HeapStrategy::HeapPtrFirstArgWithGuards,
+            stack: StackStrategy::ProbeStack,
+            symbols: Default::default(),
+            tables: Default::default(),
+            globals: Default::default(),
+            vmctx: Default::default(),
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let profile: RuntimeProfile = serde_json::from_str(json)?;
+        profile.validate().map_err(serde::de::Error::custom)?;
+        Ok(profile)
+    }
+
+    /// Checks the layout values the analyses compute with.
+    pub fn validate(&self) -> Result<(), String> {
+        // entries are scaled by 1 << entry_shift, at most 128 bytes
+        if !(0..8).contains(&self.tables.entry_shift) {
+            return Err(format!(
+                "tables.entry_shift must be between 0 and 7, not {}",
+                self.tables.entry_shift
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        RuntimeProfile::from_json(&json).map_err(|err| format!("{}: {}", path, err))
+    }
+}
+
+impl Default for RuntimeProfile {
+    fn default() -> Self {
+        RuntimeProfile::lucet()
+    }
+}
+
+impl Default for SymbolNames {
+    fn default() -> Self {
+        SymbolNames {
+            guest_table_0: Some("guest_table_0".to_string()),
+            lucet_tables: Some("lucet_tables".to_string()),
+            probestack: Some("lucet_probestack".to_string()),
+        }
+    }
+}
+
+impl Default for TableLayout {
+    fn default() -> Self {
+        // 16-byte entries of (type signature, function pointer)
+        TableLayout {
+            size_offset: 8,
+            entry_shift: 4,
+            fn_ptr_offset: 8,
+        }
+    }
+}
+
+impl Default for GlobalsLayout {
+    fn default() -> Self {
+        GlobalsLayout { max_offset: 4096 }
+    }
+}
+
+impl Default for VMCtxLayout {
+    fn default() -> Self {
+        VMCtxLayout { size: 4096 }
+    }
+}
+
+#[test]
+fn partial_profile_test() {
+    let profile = RuntimeProfile::from_json(
+        r#"{
+            "name": "patched",
+            "heap": {"VMCtxFirstArgWithGuards": {"vmctx_heap_base_ptr_offset": 64}},
+            "symbols": {"probestack": null},
+            "globals": {"max_offset": 8192}
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        profile.heap,
+        HeapStrategy::VMCtxFirstArgWithGuards {
+            vmctx_heap_base_ptr_offset: 64
+        }
+    );
+    assert_eq!(profile.symbols.probestack, None);
+    assert_eq!(
+        profile.symbols.lucet_tables,
+        Some("lucet_tables".to_string())
+    );
+    assert_eq!(profile.tables, TableLayout::default());
+    assert_eq!(profile.globals.max_offset, 8192);
+}
+
+#[test]
+fn entry_shift_test() {
+    let err = RuntimeProfile::from_json(r#"{"tables": {"entry_shift": 64}}"#).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("tables.entry_shift must be between 0 and 7, not 64"));
+    assert!(RuntimeProfile::from_json(r#"{"tables": {"entry_shift": -1}}"#).is_err());
+    assert!(RuntimeProfile::from_json(r#"{"tables": {"entry_shift": 3}}"#).is_ok());
+}
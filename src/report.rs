@@ -0,0 +1,165 @@
+//! Machine-readable verification report, written by `veriwasm -o`.
+//!
+//! The report is versioned by `REPORT_VERSION`. Fields may be added without
+//! bumping the version; renaming or removing a field, or changing its meaning,
+//! bumps it.
+
+use crate::checkers::{Property, Violation, ViolationKind};
+use serde::{Deserialize, Serialize};
+
+pub const REPORT_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModuleReport {
+    pub version: u32,
+    /// Version of VeriWasm that produced the report.
+    pub verifier_version: String,
+    pub module: ModuleSummary,
+    pub config: ReportConfig,
+    pub functions: Vec<FunctionReport>,
+    pub counts: Counts,
+    /// Sum of the per-function timings.
+    pub timings: Timings,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModuleSummary {
+    /// Empty if the module was verified from memory.
+    pub path: String,
+    /// Hex-encoded SHA-256 of the module file.
+    pub sha256: String,
+}
+
+/// The verifier configuration the report was produced with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReportConfig {
+    /// Name of the runtime profile.
+    pub profile: String,
+    pub heap_strategy: String,
+    pub stack_strategy: String,
+    pub jobs: u32,
+    pub only_func: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportVerdict {
+    Safe,
+    Unsafe,
+    /// The verifier crashed before reaching a verdict.
+    InternalError,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PropertyVerdicts {
+    pub stack: ReportVerdict,
+    pub heap: ReportVerdict,
+    pub call: ReportVerdict,
+    pub jump: ReportVerdict,
+}
+
+impl PropertyVerdicts {
+    /// Per-property verdicts for a function the checkers ran to completion on.
+    pub fn from_violations(violations: &[Violation]) -> Self {
+        let verdict = |property| {
+            if violations.iter().any(|v| v.kind.property() == property) {
+                ReportVerdict::Unsafe
+            } else {
+                ReportVerdict::Safe
+            }
+        };
+        PropertyVerdicts {
+            stack: verdict(Property::Stack),
+            heap: verdict(Property::Heap),
+            call: verdict(Property::Call),
+            jump: verdict(Property::Jump),
+        }
+    }
+
+    pub fn internal_error() -> Self {
+        PropertyVerdicts {
+            stack: ReportVerdict::InternalError,
+            heap: ReportVerdict::InternalError,
+            call: ReportVerdict::InternalError,
+            jump: ReportVerdict::InternalError,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FunctionReport {
+    pub name: String,
+    pub verdict: ReportVerdict,
+    pub properties: PropertyVerdicts,
+    /// Panic message, if `verdict` is `internal_error`.
+    pub error: Option<String>,
+    pub violations: Vec<ViolationReport>,
+    pub num_blocks: usize,
+    pub num_instructions: usize,
+    /// Opcodes the lifter did not recognize, one entry per occurrence.
+    pub unknown_opcodes: Vec<String>,
+    pub timings: Timings,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ViolationReport {
+    pub property: Property,
+    pub kind: ViolationKind,
+    pub description: String,
+    pub block_addr: u64,
+    pub addr: u64,
+    pub idx: u32,
+    /// The offending IR statement, in its `Debug` form.
+    pub stmt: String,
+    pub operands: Vec<String>,
+    /// Disassembly of the instruction at `addr`, if it could be decoded.
+    pub disassembly: Option<String>,
+}
+
+impl From<&Violation> for ViolationReport {
+    fn from(violation: &Violation) -> Self {
+        ViolationReport {
+            property: violation.kind.property(),
+            kind: violation.kind,
+            description: violation.kind.description().to_string(),
+            block_addr: violation.block_addr,
+            addr: violation.addr,
+            idx: violation.idx,
+            stmt: format!("{:?}", violation.stmt),
+            operands: violation.operands.clone(),
+            disassembly: None,
+        }
+    }
+}
+
+/// Wall-clock time of each phase, in seconds. `cfg_secs` covers lifting and
+/// jump table resolution.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Timings {
+    pub cfg_secs: f64,
+    pub stack_secs: f64,
+    pub heap_secs: f64,
+    pub call_secs: f64,
+}
+
+impl Timings {
+    pub fn total_secs(&self) -> f64 {
+        self.cfg_secs + self.stack_secs + self.heap_secs + self.call_secs
+    }
+
+    pub fn add(&mut self, other: &Timings) {
+        self.cfg_secs += other.cfg_secs;
+        self.stack_secs += other.stack_secs;
+        self.heap_secs += other.heap_secs;
+        self.call_secs += other.call_secs;
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Counts {
+    pub functions: usize,
+    pub safe: usize,
+    #[serde(rename = "unsafe")]
+    pub unsafe_: usize,
+    pub internal_errors: usize,
+}
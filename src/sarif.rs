@@ -0,0 +1,176 @@
+//! SARIF 2.1.0 output, written by `veriwasm --format sarif`.
+//!
+//! Each violation becomes a SARIF result whose rule is its `ViolationKind`.
+//! Functions the verifier crashed on are reported as tool execution
+//! notifications rather than results, since they aren't known to be unsafe.
+
+use crate::checkers::ViolationKind;
+use crate::report::{ModuleReport, ReportVerdict};
+use serde_json::{json, Value};
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+fn rule_id(kind: ViolationKind) -> String {
+    format!("{:?}", kind)
+}
+
+fn rules() -> Vec<Value> {
+    ViolationKind::ALL
+        .iter()
+        .map(|kind| {
+            json!({
+                "id": rule_id(*kind),
+                "shortDescription": { "text": kind.description() },
+                "defaultConfiguration": { "level": "error" },
+                "properties": { "property": kind.property() },
+            })
+        })
+        .collect()
+}
+
+pub fn to_sarif(report: &ModuleReport) -> Value {
+    let mut results = vec![];
+    let mut notifications = vec![];
+    for function in &report.functions {
+        if function.verdict == ReportVerdict::InternalError {
+            notifications.push(json!({
+                "level": "error",
+                "message": {
+                    "text": format!(
+                        "{}: internal error: {}",
+                        function.name,
+                        function.error.as_deref().unwrap_or("unknown")
+                    )
+                },
+            }));
+        }
+        for violation in &function.violations {
+            let disassembly = violation.disassembly.as_deref().unwrap_or("<unknown>");
+            results.push(json!({
+                "ruleId": rule_id(violation.kind),
+                "ruleIndex": ViolationKind::ALL.iter().position(|k| *k == violation.kind),
+                "level": "error",
+                "message": {
+                    "text": format!(
+                        "{} at 0x{:x}: {} (`{}`)",
+                        function.name, violation.addr, violation.description, disassembly
+                    )
+                },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": report.module.path },
+                        "address": { "absoluteAddress": violation.addr },
+                    },
+                    "logicalLocations": [{
+                        "name": function.name,
+                        "kind": "function",
+                    }],
+                }],
+                "properties": {
+                    "disassembly": violation.disassembly,
+                    "blockAddress": violation.block_addr,
+                    "stmt": violation.stmt,
+                    "operands": violation.operands,
+                },
+            }));
+        }
+    }
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "VeriWasm",
+                    "version": report.verifier_version,
+                    "informationUri": "https://github.com/PLSysSec/veriwasm",
+                    "rules": rules(),
+                }
+            },
+            "artifacts": [{
+                "location": { "uri": report.module.path },
+                "hashes": { "sha-256": report.module.sha256 },
+            }],
+            "invocations": [{
+                "executionSuccessful": report.counts.internal_errors == 0,
+                "toolExecutionNotifications": notifications,
+            }],
+            "results": results,
+        }]
+    })
+}
+
+#[test]
+fn sarif_rule_index_test() {
+    use crate::checkers::Violation;
+    use crate::utils::lifter::Stmt;
+    use crate::verify::{module_report, FunctionResult, Verdict, VerifyConfig};
+
+    let violation = |addr, kind| Violation {
+        func: "f".to_string(),
+        block_addr: 0,
+        addr,
+        idx: 0,
+        stmt: Stmt::Ret,
+        operands: vec![],
+        kind,
+    };
+    let result = FunctionResult {
+        func_name: "f".to_string(),
+        num_blocks: 1,
+        num_instructions: 1,
+        timings: Default::default(),
+        unknown_opcodes: vec![],
+        disassembly: vec![(4, "ret".to_string())].into_iter().collect(),
+        dot: None,
+        verdict: Verdict::Unsafe(
+            ViolationKind::ALL
+                .iter()
+                .rev()
+                .map(|kind| violation(4, *kind))
+                .collect(),
+        ),
+    };
+    let mut report = module_report(b"", &VerifyConfig::default(), &[result]);
+    report.module.path = "m.so".to_string();
+    let sarif = to_sarif(&report);
+    let run = &sarif["runs"][0];
+
+    let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+    assert_eq!(rules.len(), ViolationKind::ALL.len());
+    for (rule, kind) in rules.iter().zip(ViolationKind::ALL.iter()) {
+        assert_eq!(rule["id"], rule_id(*kind));
+        assert_eq!(rule["shortDescription"]["text"], kind.description());
+    }
+
+    let results = run["results"].as_array().unwrap();
+    assert_eq!(results.len(), ViolationKind::ALL.len());
+    for result in results {
+        let index = result["ruleIndex"].as_u64().unwrap() as usize;
+        assert_eq!(result["ruleId"], rules[index]["id"]);
+    }
+    assert_eq!(
+        results[0],
+        json!({
+            "ruleId": "JumpTableEdgeMismatch",
+            "ruleIndex": ViolationKind::ALL.len() - 1,
+            "level": "error",
+            "message": {
+                "text": "f at 0x4: jump table targets do not match the block's CFG edges (`ret`)"
+            },
+            "locations": [{
+                "physicalLocation": {
+                    "artifactLocation": { "uri": "m.so" },
+                    "address": { "absoluteAddress": 4 },
+                },
+                "logicalLocations": [{ "name": "f", "kind": "function" }],
+            }],
+            "properties": {
+                "disassembly": "ret",
+                "blockAddress": 0,
+                "stmt": "Ret",
+                "operands": [],
+            },
+        })
+    );
+}
@@ -0,0 +1,300 @@
+//! Wasmtime precompiled modules (`.cwasm`).
+//!
+//! A `.cwasm` file is an ELF object whose text section holds every compiled
+//! Wasm function and the trampolines between the host and Wasm ABIs. Where
+//! each of them is comes from the artifact's `.wasmtime.info` section, the
+//! bincode (fixed-width integers, little-endian) encoding of Wasmtime's
+//! `CompiledModuleInfo`:
+//!
+//! ```text
+//! module: Module
+//! funcs: u64 count, then per defined function
+//!     start_srcloc: u32
+//!     stack_maps: u64 count, then per entry
+//!         code_offset: u32, bits: u64 count + count * u32, mapped_words: u32
+//!     wasm_func_loc: FunctionLoc
+//!     array_to_wasm_trampoline: Option<FunctionLoc>
+//!     native_to_wasm_trampoline: Option<FunctionLoc>
+//! wasm_to_native_trampolines: u64 count, then per entry
+//!     signature: u32, loc: FunctionLoc
+//! func_names: u64 count, then per entry
+//!     idx: u32, offset: u32, len: u32
+//! meta: native_debug_info_present: bool, has_unparsed_debuginfo: bool,
+//!     code_section_offset: u64, has_wasm_debuginfo: bool,
+//!     dwarf: u64 count, then per entry
+//!         id: u8, start: u64, end: u64
+//! ```
+//!
+//! where a `FunctionLoc` is a `u32` offset into the text section and a `u32`
+//! length, and an `Option` is a `u8` tag followed by the value if it is 1.
+//! `Module` has no fixed size and changes between Wasmtime versions, so it is
+//! not decoded; the function table starts at the one offset from which the
+//! rest of the section decodes exactly. ELF symbols are not used, so stripped
+//! artifacts are supported.
+
+use object::{Object, ObjectSection};
+
+/// Sections that only Wasmtime artifacts have.
+const WASMTIME_SECTIONS: [&str; 2] = [".wasmtime.engine", ".wasmtime.info"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CwasmFuncKind {
+    /// A compiled Wasm function, which is verified.
+    Wasm,
+    /// A host-to-Wasm or Wasm-to-host trampoline. These don't follow the
+    /// Wasm ABI the analyses assume, so they are not verified.
+    Trampoline,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CwasmFunc {
+    pub name: String,
+    pub addr: u64,
+    pub size: u64,
+    pub kind: CwasmFuncKind,
+}
+
+pub fn is_cwasm(data: &[u8]) -> bool {
+    match object::File::parse(data) {
+        Ok(file) => WASMTIME_SECTIONS
+            .iter()
+            .any(|name| file.section_by_name(name).is_some()),
+        Err(_) => false,
+    }
+}
+
+// A bincode decoder that fails on anything the layout above doesn't allow.
+struct InfoReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    text_size: u64,
+}
+
+impl InfoReader<'_> {
+    fn bytes(&mut self, n: usize) -> Option<&[u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.bytes(4)?);
+        Some(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.bytes(8)?);
+        Some(u64::from_le_bytes(buf))
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    // A sequence length, which can't exceed the bytes left since every
+    // element takes at least `min_size` of them.
+    fn len(&mut self, min_size: usize) -> Option<usize> {
+        let len = self.u64()?;
+        if len > ((self.data.len() - self.pos) / min_size) as u64 {
+            return None;
+        }
+        Some(len as usize)
+    }
+
+    // (offset, length) within the text section
+    fn loc(&mut self) -> Option<(u64, u64)> {
+        let start = self.u32()? as u64;
+        let len = self.u32()? as u64;
+        if start + len > self.text_size {
+            return None;
+        }
+        Some((start, len))
+    }
+
+    fn option_loc(&mut self) -> Option<Option<(u64, u64)>> {
+        if self.bool()? {
+            Some(Some(self.loc()?))
+        } else {
+            Some(None)
+        }
+    }
+}
+
+// Decodes everything in `.wasmtime.info` after `module`, starting at `pos`.
+// Returns the functions and trampolines by text section offset.
+fn read_tail(info: &[u8], pos: usize, text_size: u64) -> Option<Vec<CwasmFunc>> {
+    let mut reader = InfoReader {
+        data: info,
+        pos,
+        text_size,
+    };
+    let mut funcs = vec![];
+    let trampoline = |funcs: &mut Vec<CwasmFunc>, name: String, loc: Option<(u64, u64)>| {
+        if let Some((addr, size)) = loc {
+            funcs.push(CwasmFunc {
+                name,
+                addr,
+                size,
+                kind: CwasmFuncKind::Trampoline,
+            });
+        }
+    };
+
+    for idx in 0..reader.len(22)? {
+        reader.u32()?;
+        for _ in 0..reader.len(16)? {
+            reader.u32()?;
+            let bits = reader.len(4)?;
+            reader.bytes(bits * 4)?;
+            reader.u32()?;
+        }
+        let (addr, size) = reader.loc()?;
+        funcs.push(CwasmFunc {
+            name: format!("function[{}]", idx),
+            addr,
+            size,
+            kind: CwasmFuncKind::Wasm,
+        });
+        let array_to_wasm = reader.option_loc()?;
+        trampoline(
+            &mut funcs,
+            format!("array_to_wasm_trampoline[{}]", idx),
+            array_to_wasm,
+        );
+        let native_to_wasm = reader.option_loc()?;
+        trampoline(
+            &mut funcs,
+            format!("native_to_wasm_trampoline[{}]", idx),
+            native_to_wasm,
+        );
+    }
+    for idx in 0..reader.len(12)? {
+        reader.u32()?;
+        let loc = reader.loc()?;
+        trampoline(
+            &mut funcs,
+            format!("wasm_to_native_trampoline[{}]", idx),
+            Some(loc),
+        );
+    }
+    let names = reader.len(12)?;
+    reader.bytes(names * 12)?;
+    reader.bool()?;
+    reader.bool()?;
+    reader.u64()?;
+    reader.bool()?;
+    let dwarf = reader.len(17)?;
+    reader.bytes(dwarf * 17)?;
+
+    if reader.pos == info.len() {
+        Some(funcs)
+    } else {
+        None
+    }
+}
+
+/// Lists the functions and trampolines in a `.cwasm` file, sorted by address.
+pub fn cwasm_functions(data: &[u8]) -> Result<Vec<CwasmFunc>, String> {
+    let file = object::File::parse(data).map_err(|err| err.to_string())?;
+    let info = file
+        .section_by_name(".wasmtime.info")
+        .ok_or_else(|| "no .wasmtime.info section".to_string())?;
+    let info = info.data().map_err(|err| err.to_string())?;
+    let (text_addr, text_size) = file
+        .section_by_name(".text")
+        .map_or((0, 0), |text| (text.address(), text.size()));
+
+    let mut tables = (0..info.len()).filter_map(|pos| read_tail(info, pos, text_size));
+    let mut funcs = match (tables.next(), tables.next()) {
+        (Some(funcs), None) => funcs,
+        (None, _) => return Err("no function table in .wasmtime.info".to_string()),
+        (Some(_), Some(_)) => {
+            return Err("more than one function table in .wasmtime.info".to_string())
+        }
+    };
+    for func in funcs.iter_mut() {
+        func.addr += text_addr;
+    }
+    funcs.sort_by_key(|func| func.addr);
+    Ok(funcs)
+}
+
+#[test]
+fn cwasm_functions_test() {
+    use crate::utils::relocs::ET_REL;
+    use crate::utils::testing::elf_object;
+
+    let loc = |start: u32, len: u32| [start.to_le_bytes(), len.to_le_bytes()].concat();
+    let mut info = vec![0xab; 5]; // module
+    info.extend(1u64.to_le_bytes()); // funcs
+    info.extend(0u32.to_le_bytes());
+    info.extend(0u64.to_le_bytes());
+    info.extend(loc(0, 16));
+    info.push(1);
+    info.extend(loc(16, 8));
+    info.push(0);
+    info.extend(1u64.to_le_bytes()); // wasm_to_native_trampolines
+    info.extend(0u32.to_le_bytes());
+    info.extend(loc(32, 8));
+    info.extend(0u64.to_le_bytes()); // func_names
+    info.extend([0, 0]); // meta
+    info.extend(0u64.to_le_bytes());
+    info.push(0);
+    info.extend(0u64.to_le_bytes());
+
+    // a stripped artifact: no symbol table
+    let data = elf_object(
+        ET_REL,
+        &[
+            (".text", 1, 0, 0, 0, vec![0xc3; 48]),
+            (".wasmtime.info", 1, 0, 0, 0, info.clone()),
+        ],
+    );
+    assert!(is_cwasm(&data));
+    let funcs = cwasm_functions(&data).unwrap();
+    let funcs: Vec<_> = funcs
+        .iter()
+        .map(|func| (func.name.as_str(), func.addr, func.size, func.kind))
+        .collect();
+    assert_eq!(
+        funcs,
+        vec![
+            ("function[0]", 0, 16, CwasmFuncKind::Wasm),
+            (
+                "array_to_wasm_trampoline[0]",
+                16,
+                8,
+                CwasmFuncKind::Trampoline
+            ),
+            (
+                "wasm_to_native_trampoline[0]",
+                32,
+                8,
+                CwasmFuncKind::Trampoline
+            ),
+        ]
+    );
+
+    // the trampoline at 32 doesn't fit in the text section
+    let data = elf_object(
+        ET_REL,
+        &[
+            (".text", 1, 0, 0, 0, vec![0xc3; 36]),
+            (".wasmtime.info", 1, 0, 0, 0, info),
+        ],
+    );
+    assert_eq!(
+        cwasm_functions(&data),
+        Err("no function table in .wasmtime.info".to_string())
+    );
+}
@@ -0,0 +1,112 @@
+use crate::analyses::AnalysisResult;
+use crate::utils::lifter::IRMap;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fmt::Write;
+use yaxpeax_core::analyses::control_flow::VW_CFG;
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Graphviz gets slow and unreadable with enormous labels, so abstract states
+// are wrapped and cut off.
+fn wrap(s: &str, width: usize, max_lines: usize) -> Vec<String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut lines: Vec<String> = chars
+        .chunks(width)
+        .map(|chunk| chunk.iter().collect())
+        .collect();
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        lines.push("...".to_string());
+    }
+    lines
+}
+
+/// Formats the abstract state at each block entry, for `cfg_to_dot`.
+pub fn format_states<State: Debug>(states: &AnalysisResult<State>) -> HashMap<u64, String> {
+    states
+        .iter()
+        .map(|(block_addr, state)| (*block_addr, format!("{:?}", state)))
+        .collect()
+}
+
+/// Renders `cfg` as a DOT graph, with each block labelled by its lifted
+/// statements. If `states` is given, each block is also labelled with the
+/// abstract state at block entry. Instructions at `highlight` addresses are
+/// drawn in red, along with the blocks that contain them.
+pub fn cfg_to_dot(
+    func_name: &str,
+    cfg: &VW_CFG,
+    irmap: &IRMap,
+    states: Option<&HashMap<u64, String>>,
+    highlight: &[u64],
+) -> String {
+    let mut block_addrs: Vec<u64> = cfg.graph.nodes().collect();
+    block_addrs.sort();
+
+    let mut out = String::new();
+    writeln!(out, "digraph \"{}\" {{", func_name.replace('"', "\\\"")).unwrap();
+    writeln!(out, "    node [shape=plaintext fontname=\"monospace\"];").unwrap();
+    for block_addr in &block_addrs {
+        let ir_block = match irmap.get(block_addr) {
+            Some(ir_block) => ir_block,
+            None => continue,
+        };
+        let failing = ir_block.iter().any(|(addr, _)| highlight.contains(addr));
+        let border = if failing { "red" } else { "black" };
+        writeln!(
+            out,
+            "    \"0x{:x}\" [label=<<table border=\"1\" cellborder=\"0\" color=\"{}\">",
+            block_addr, border
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "        <tr><td align=\"left\"><b>block 0x{:x}</b></td></tr>",
+            block_addr
+        )
+        .unwrap();
+        if let Some(state) = states.and_then(|states| states.get(block_addr)) {
+            for line in wrap(state, 100, 20) {
+                writeln!(
+                    out,
+                    "        <tr><td align=\"left\"><font color=\"blue\">{}</font></td></tr>",
+                    escape_html(&line)
+                )
+                .unwrap();
+            }
+        }
+        for (addr, ir_stmts) in ir_block {
+            let bgcolor = if highlight.contains(addr) {
+                " bgcolor=\"#ffcccc\""
+            } else {
+                ""
+            };
+            for ir_stmt in ir_stmts {
+                writeln!(
+                    out,
+                    "        <tr><td align=\"left\"{}>0x{:x}: {}</td></tr>",
+                    bgcolor,
+                    addr,
+                    escape_html(&format!("{}", ir_stmt))
+                )
+                .unwrap();
+            }
+        }
+        writeln!(out, "    </table>>];").unwrap();
+    }
+    for block_addr in &block_addrs {
+        let mut succ_addrs: Vec<u64> = cfg.graph.neighbors(*block_addr).collect();
+        succ_addrs.sort();
+        for succ_addr in succ_addrs {
+            writeln!(out, "    \"0x{:x}\" -> \"0x{:x}\";", block_addr, succ_addr).unwrap();
+        }
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
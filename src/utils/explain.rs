@@ -0,0 +1,52 @@
+use crate::analyses::{AbstractAnalyzer, AnalysisResult};
+use crate::lattices::reachingdefslattice::LocIdx;
+use crate::lattices::{Lattice, VarState};
+use crate::utils::lifter::IRMap;
+use crate::utils::utils::disassemble_at;
+use std::fmt::Debug;
+use std::io::Write;
+use yaxpeax_core::memory::repr::process::ModuleData;
+
+/// Writes every instruction of a function next to its lifted IR and the
+/// abstract state before and after it. States are replayed with `aexec` from
+/// each block's entry state in `result`, the same way
+/// `Checker::check_state_at_statements` does.
+pub fn explain<State, Analyzer>(
+    program: &ModuleData,
+    irmap: &IRMap,
+    analyzer: &Analyzer,
+    result: &AnalysisResult<State>,
+    out: &mut dyn Write,
+) -> std::io::Result<()>
+where
+    State: Lattice + VarState + Clone + Debug,
+    Analyzer: AbstractAnalyzer<State>,
+{
+    let mut block_addrs: Vec<&u64> = irmap.keys().collect();
+    block_addrs.sort();
+    for block_addr in block_addrs {
+        writeln!(out, "block 0x{:x}:", block_addr)?;
+        let mut state = match result.get(block_addr) {
+            Some(state) => state.clone(),
+            None => {
+                writeln!(out, "    (unreachable)")?;
+                continue;
+            }
+        };
+        for (addr, ir_stmts) in irmap.get(block_addr).unwrap() {
+            let disassembly = disassemble_at(program, *addr).unwrap_or("<unknown>".to_string());
+            writeln!(out, "  0x{:x}: {}", addr, disassembly)?;
+            writeln!(out, "      before: {:?}", state)?;
+            for (idx, ir_stmt) in ir_stmts.iter().enumerate() {
+                writeln!(out, "      ir:     {}", ir_stmt)?;
+                let loc_idx = LocIdx {
+                    addr: *addr,
+                    idx: idx as u32,
+                };
+                analyzer.aexec(&mut state, ir_stmt, &loc_idx);
+            }
+            writeln!(out, "      after:  {:?}", state)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,498 @@
+//! A textual syntax for the lifted IR, with a printer and a parser.
+//!
+//! Registers are `r<num>:<bits>` and immediates `<value>:<i|u><bits>`, e.g.
+//! `r7:64` and `-8:i32`; a size that is not known is `?`. Memory operands are
+//! `mem<bits>[...]`, with the same forms as `MemArgs`: `[a]`, `[a + b]`,
+//! `[a + b + c]`, `[a + b * c]` and `[a + b * c + d]`. In a memory operand a
+//! displacement that is a signed 32-bit immediate, as the lifter produces,
+//! is written without its type. Statements are
+//!
+//! ```text
+//! r0:64 <- mov mem32[r7:64 + r1:64 + 8]
+//! r4:64 <- sub r4:64, 16:i64
+//! r16:8 <- clear(r1:64, r2:64)
+//! br jnz 4096:i64
+//! call r0:64
+//! probestack 8192
+//! ret
+//! undefined
+//! ```
+//!
+//! A function is a list of blocks, each with the instructions it was lifted
+//! from, one line per statement:
+//!
+//! ```text
+//! block 0x0 -> 0x7, 0x9
+//!   0x0: r1:32 <- mov r6:32
+//!   0x2: r16:8 <- cmp r1:64, 16:i32
+//!   0x5: br jb 9:i64
+//! ```
+//!
+//! An instruction lifted to no statements is written as its address alone.
+//! `unknown` statements, which record an opcode the lifter didn't know, are
+//! printed but can't be parsed.
+
+use crate::utils::lifter::{
+    Binopcode, IRBlock, IRMap, ImmType, MemArg, MemArgs, Stmt, Unopcode, ValSize, Value,
+};
+use petgraph::graphmap::GraphMap;
+use std::collections::BTreeMap;
+use std::fmt;
+use yaxpeax_core::analyses::control_flow::{VW_Block, VW_CFG};
+use yaxpeax_x86::long_mode::Opcode;
+
+const BRANCH_OPCODES: [Opcode; 17] = [
+    Opcode::JMP,
+    Opcode::JO,
+    Opcode::JNO,
+    Opcode::JB,
+    Opcode::JNB,
+    Opcode::JZ,
+    Opcode::JNZ,
+    Opcode::JA,
+    Opcode::JNA,
+    Opcode::JS,
+    Opcode::JNS,
+    Opcode::JP,
+    Opcode::JNP,
+    Opcode::JL,
+    Opcode::JGE,
+    Opcode::JLE,
+    Opcode::JG,
+];
+
+fn opcode_name(opcode: &Opcode) -> String {
+    format!("{:?}", opcode).to_lowercase()
+}
+
+impl fmt::Display for ValSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValSize::SizeOther => write!(f, "?"),
+            size => write!(f, "{}", size.to_u32()),
+        }
+    }
+}
+
+fn fmt_imm(f: &mut fmt::Formatter, immtype: &ImmType, size: &ValSize, v: i64) -> fmt::Result {
+    match immtype {
+        ImmType::Signed => write!(f, "{}:i{}", v, size),
+        ImmType::Unsigned => write!(f, "{}:u{}", v, size),
+    }
+}
+
+impl fmt::Display for MemArg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemArg::Reg(regnum, size) => write!(f, "r{}:{}", regnum, size),
+            MemArg::Imm(ImmType::Signed, ValSize::Size32, v) => write!(f, "{}", v),
+            MemArg::Imm(immtype, size, v) => fmt_imm(f, immtype, size, *v),
+        }
+    }
+}
+
+impl fmt::Display for MemArgs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemArgs::Mem1Arg(a) => write!(f, "[{}]", a),
+            MemArgs::Mem2Args(a, b) => write!(f, "[{} + {}]", a, b),
+            MemArgs::Mem3Args(a, b, c) => write!(f, "[{} + {} + {}]", a, b, c),
+            MemArgs::MemScale(a, b, c) => write!(f, "[{} + {} * {}]", a, b, c),
+            MemArgs::MemScaleDisp(a, b, c, d) => write!(f, "[{} + {} * {} + {}]", a, b, c, d),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Mem(size, memargs) => write!(f, "mem{}{}", size, memargs),
+            Value::Reg(regnum, size) => write!(f, "r{}:{}", regnum, size),
+            Value::Imm(immtype, size, v) => fmt_imm(f, immtype, size, *v),
+            Value::RIPConst => write!(f, "ripconst"),
+        }
+    }
+}
+
+impl fmt::Display for Unopcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Unopcode::Mov => write!(f, "mov"),
+            Unopcode::Movsx => write!(f, "movsx"),
+        }
+    }
+}
+
+impl fmt::Display for Binopcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Binopcode::Test => "test",
+            Binopcode::Rol => "rol",
+            Binopcode::Cmp => "cmp",
+            Binopcode::Shl => "shl",
+            Binopcode::Shr => "shr",
+            Binopcode::And => "and",
+            Binopcode::Add => "add",
+            Binopcode::Sub => "sub",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stmt::Clear(dst, srcs) => {
+                let srcs: Vec<String> = srcs.iter().map(|src| src.to_string()).collect();
+                write!(f, "{} <- clear({})", dst, srcs.join(", "))
+            }
+            Stmt::Unop(opcode, dst, src) => write!(f, "{} <- {} {}", dst, opcode, src),
+            Stmt::Binop(opcode, dst, src1, src2) => {
+                write!(f, "{} <- {} {}, {}", dst, opcode, src1, src2)
+            }
+            Stmt::Undefined => write!(f, "undefined"),
+            Stmt::Ret => write!(f, "ret"),
+            Stmt::Branch(opcode, target) => write!(f, "br {} {}", opcode_name(opcode), target),
+            Stmt::Call(target) => write!(f, "call {}", target),
+            Stmt::ProbeStack(size) => write!(f, "probestack {}", size),
+            Stmt::UnknownOpcode(opcode) => write!(f, "unknown {}", opcode_name(opcode)),
+        }
+    }
+}
+
+/// Prints a function's blocks, entry block first and the rest by address.
+pub fn print_ir(cfg: &VW_CFG, irmap: &IRMap) -> String {
+    let mut block_addrs: Vec<u64> = irmap.keys().cloned().collect();
+    block_addrs.sort_by_key(|addr| (*addr != cfg.entrypoint, *addr));
+    let mut out = String::new();
+    for block_addr in block_addrs {
+        let mut succ_addrs: Vec<u64> = cfg.graph.neighbors(block_addr).collect();
+        succ_addrs.sort();
+        out.push_str(&format!("block 0x{:x}", block_addr));
+        if !succ_addrs.is_empty() {
+            let succs: Vec<String> = succ_addrs
+                .iter()
+                .map(|addr| format!("0x{:x}", addr))
+                .collect();
+            out.push_str(&format!(" -> {}", succs.join(", ")));
+        }
+        out.push('\n');
+        for (addr, stmts) in &irmap[&block_addr] {
+            if stmts.is_empty() {
+                out.push_str(&format!("  0x{:x}:\n", addr));
+            }
+            for stmt in stmts {
+                out.push_str(&format!("  0x{:x}: {}\n", addr, stmt));
+            }
+        }
+    }
+    out
+}
+
+fn parse_int(s: &str) -> Result<i64, String> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let v = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).map(|v| v as i64),
+        None => digits.parse::<i64>(),
+    }
+    .map_err(|_| format!("bad integer: {}", s))?;
+    Ok(if negative { -v } else { v })
+}
+
+fn parse_addr(s: &str) -> Result<u64, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).map_err(|_| format!("bad address: {}", s)),
+        None => Err(format!("bad address: {}", s)),
+    }
+}
+
+fn parse_size(s: &str) -> Result<ValSize, String> {
+    match s {
+        "8" => Ok(ValSize::Size8),
+        "16" => Ok(ValSize::Size16),
+        "32" => Ok(ValSize::Size32),
+        "64" => Ok(ValSize::Size64),
+        "128" => Ok(ValSize::Size128),
+        "256" => Ok(ValSize::Size256),
+        "512" => Ok(ValSize::Size512),
+        "?" => Ok(ValSize::SizeOther),
+        _ => Err(format!("bad size: {}", s)),
+    }
+}
+
+fn parse_reg(s: &str) -> Result<Option<(u8, ValSize)>, String> {
+    if let Some(reg) = s.strip_prefix('r') {
+        if let Some((regnum, size)) = reg.split_once(':') {
+            if let Ok(regnum) = regnum.parse::<u8>() {
+                return Ok(Some((regnum, parse_size(size)?)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn parse_imm(s: &str) -> Result<(ImmType, ValSize, i64), String> {
+    let (v, ty) = s
+        .split_once(':')
+        .ok_or_else(|| format!("immediate without a type: {}", s))?;
+    let immtype = match &ty[..1] {
+        "i" => ImmType::Signed,
+        "u" => ImmType::Unsigned,
+        _ => return Err(format!("bad immediate type: {}", ty)),
+    };
+    Ok((immtype, parse_size(&ty[1..])?, parse_int(v)?))
+}
+
+fn parse_memarg(s: &str) -> Result<MemArg, String> {
+    if let Some((regnum, size)) = parse_reg(s)? {
+        return Ok(MemArg::Reg(regnum, size));
+    }
+    if s.contains(':') {
+        let (immtype, size, v) = parse_imm(s)?;
+        Ok(MemArg::Imm(immtype, size, v))
+    } else {
+        Ok(MemArg::Imm(ImmType::Signed, ValSize::Size32, parse_int(s)?))
+    }
+}
+
+fn parse_memargs(s: &str) -> Result<MemArgs, String> {
+    let mut terms = vec![];
+    for term in s.split(" + ") {
+        match term.split_once(" * ") {
+            Some((idx, scale)) => terms.push((parse_memarg(idx)?, Some(parse_memarg(scale)?))),
+            None => terms.push((parse_memarg(term)?, None)),
+        }
+    }
+    let memargs = match terms.as_slice() {
+        [(a, None)] => MemArgs::Mem1Arg(a.clone()),
+        [(a, None), (b, None)] => MemArgs::Mem2Args(a.clone(), b.clone()),
+        [(a, None), (b, None), (c, None)] => MemArgs::Mem3Args(a.clone(), b.clone(), c.clone()),
+        [(a, None), (b, Some(c))] => MemArgs::MemScale(a.clone(), b.clone(), c.clone()),
+        [(a, None), (b, Some(c)), (d, None)] => {
+            MemArgs::MemScaleDisp(a.clone(), b.clone(), c.clone(), d.clone())
+        }
+        _ => return Err(format!("bad memory operand: [{}]", s)),
+    };
+    Ok(memargs)
+}
+
+pub fn parse_value(s: &str) -> Result<Value, String> {
+    let s = s.trim();
+    if s == "ripconst" {
+        return Ok(Value::RIPConst);
+    }
+    if let Some(mem) = s.strip_prefix("mem") {
+        let (size, rest) = mem
+            .split_once('[')
+            .ok_or_else(|| format!("bad memory operand: {}", s))?;
+        let memargs = rest
+            .strip_suffix(']')
+            .ok_or_else(|| format!("bad memory operand: {}", s))?;
+        return Ok(Value::Mem(parse_size(size)?, parse_memargs(memargs)?));
+    }
+    if let Some((regnum, size)) = parse_reg(s)? {
+        return Ok(Value::Reg(regnum, size));
+    }
+    let (immtype, size, v) = parse_imm(s)?;
+    Ok(Value::Imm(immtype, size, v))
+}
+
+fn parse_unopcode(s: &str) -> Option<Unopcode> {
+    match s {
+        "mov" => Some(Unopcode::Mov),
+        "movsx" => Some(Unopcode::Movsx),
+        _ => None,
+    }
+}
+
+fn parse_binopcode(s: &str) -> Option<Binopcode> {
+    match s {
+        "test" => Some(Binopcode::Test),
+        "rol" => Some(Binopcode::Rol),
+        "cmp" => Some(Binopcode::Cmp),
+        "shl" => Some(Binopcode::Shl),
+        "shr" => Some(Binopcode::Shr),
+        "and" => Some(Binopcode::And),
+        "add" => Some(Binopcode::Add),
+        "sub" => Some(Binopcode::Sub),
+        _ => None,
+    }
+}
+
+pub fn parse_stmt(s: &str) -> Result<Stmt, String> {
+    let s = s.trim();
+    if let Some((dst, rhs)) = s.split_once(" <- ") {
+        let dst = parse_value(dst)?;
+        if let Some(srcs) = rhs.strip_prefix("clear(") {
+            let srcs = srcs
+                .strip_suffix(')')
+                .ok_or_else(|| format!("bad clear: {}", s))?;
+            let srcs = if srcs.is_empty() {
+                vec![]
+            } else {
+                srcs.split(", ")
+                    .map(parse_value)
+                    .collect::<Result<Vec<Value>, String>>()?
+            };
+            return Ok(Stmt::Clear(dst, srcs));
+        }
+        let (opcode, operands) = rhs
+            .split_once(' ')
+            .ok_or_else(|| format!("bad statement: {}", s))?;
+        if let Some(opcode) = parse_unopcode(opcode) {
+            return Ok(Stmt::Unop(opcode, dst, parse_value(operands)?));
+        }
+        if let Some(opcode) = parse_binopcode(opcode) {
+            let (src1, src2) = operands
+                .split_once(", ")
+                .ok_or_else(|| format!("bad statement: {}", s))?;
+            return Ok(Stmt::Binop(
+                opcode,
+                dst,
+                parse_value(src1)?,
+                parse_value(src2)?,
+            ));
+        }
+        return Err(format!("unknown operation: {}", opcode));
+    }
+    match s.split_once(' ') {
+        None if s == "ret" => Ok(Stmt::Ret),
+        None if s == "undefined" => Ok(Stmt::Undefined),
+        Some(("call", target)) => Ok(Stmt::Call(parse_value(target)?)),
+        Some(("probestack", size)) => Ok(Stmt::ProbeStack(parse_int(size)? as u64)),
+        Some(("br", branch)) => {
+            let (opcode, target) = branch
+                .split_once(' ')
+                .ok_or_else(|| format!("bad branch: {}", s))?;
+            let opcode = BRANCH_OPCODES
+                .iter()
+                .find(|op| opcode_name(op) == opcode)
+                .ok_or_else(|| format!("unknown branch: {}", opcode))?;
+            Ok(Stmt::Branch(*opcode, parse_value(target)?))
+        }
+        _ => Err(format!("bad statement: {}", s)),
+    }
+}
+
+/// Parses a function printed by `print_ir`. The first block is the entry.
+pub fn parse_ir(text: &str) -> Result<(VW_CFG, IRMap), String> {
+    let mut cfg = VW_CFG {
+        entrypoint: 0,
+        blocks: BTreeMap::new(),
+        graph: GraphMap::new(),
+    };
+    let mut irmap = IRMap::new();
+    let mut edges = vec![];
+    let mut current: Option<(u64, IRBlock)> = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix("block ") {
+            if let Some((start, block)) = current.take() {
+                irmap.insert(start, block);
+            }
+            let (start, succs) = match header.split_once(" -> ") {
+                Some((start, succs)) => (start, succs.split(", ").collect()),
+                None => (header, vec![]),
+            };
+            let start = parse_addr(start)?;
+            if irmap.is_empty() {
+                cfg.entrypoint = start;
+            }
+            for succ in succs {
+                edges.push((start, parse_addr(succ)?));
+            }
+            cfg.graph.add_node(start);
+            current = Some((start, vec![]));
+            continue;
+        }
+        let (_, block) = current
+            .as_mut()
+            .ok_or_else(|| format!("statement outside a block: {}", line))?;
+        let (addr, stmt) = line
+            .split_once(':')
+            .ok_or_else(|| format!("statement without an address: {}", line))?;
+        let addr = parse_addr(addr)?;
+        if block.last().map(|(last, _)| *last) != Some(addr) {
+            block.push((addr, vec![]));
+        }
+        if !stmt.trim().is_empty() {
+            block.last_mut().unwrap().1.push(parse_stmt(stmt)?);
+        }
+    }
+    if let Some((start, block)) = current.take() {
+        irmap.insert(start, block);
+    }
+    for (start, block) in &irmap {
+        let end = block.last().map_or(*start, |(addr, _)| *addr);
+        cfg.blocks.insert(*start, VW_Block { start: *start, end });
+    }
+    for (from, to) in edges {
+        if !irmap.contains_key(&to) {
+            return Err(format!("edge to a missing block: 0x{:x}", to));
+        }
+        cfg.graph.add_edge(from, to, ());
+    }
+    Ok((cfg, irmap))
+}
+
+#[test]
+fn ir_text_roundtrip_test() {
+    let stmts = [
+        "r0:64 <- mov mem32[r7:64 + r1:64 + 8]",
+        "r0:64 <- movsx mem16[r7:64 + -4]",
+        "r4:64 <- sub r4:64, 16:i64",
+        "r16:8 <- clear(r1:64, mem8[r6:64])",
+        "r2:? <- clear()",
+        "mem128[r7:64 + r1:64 * 4:i64 + -16:i64] <- clear(r3:128)",
+        "r1:64 <- mov mem64[0:u32 + r2:64 * 8]",
+        "r0:64 <- mov ripconst",
+        "br jnz 4096:i64",
+        "call r0:64",
+        "probestack 8192",
+        "ret",
+        "undefined",
+    ];
+    for stmt in stmts.iter() {
+        assert_eq!(parse_stmt(stmt).unwrap().to_string(), *stmt);
+    }
+    assert!(parse_stmt("r0:64 <- mov mem32[r7:64 * 4 * 2]").is_err());
+    assert!(parse_stmt("unknown cpuid").is_err());
+}
+
+#[test]
+fn ir_text_function_test() {
+    use crate::analyses::heap_analyzer::HeapAnalyzer;
+    use crate::analyses::run_worklist;
+    use crate::checkers::heap_checker::check_heap;
+    use crate::utils::utils::LucetMetadata;
+    use crate::HeapStrategy;
+
+    let text = "\
+block 0x0 -> 0x7
+  0x0: r1:32 <- mov r6:32
+  0x2:
+  0x3: r0:32 <- mov mem32[r7:64 + r1:64]
+block 0x7
+  0x7: ret
+";
+    let (cfg, irmap) = parse_ir(text).unwrap();
+    assert_eq!(print_ir(&cfg, &irmap), text);
+
+    let analyzer = HeapAnalyzer {
+        metadata: LucetMetadata::default(),
+        strategy: HeapStrategy::HeapPtrFirstArgWithGuards,
+    };
+    let result = run_worklist(&cfg, &irmap, &analyzer);
+    assert!(check_heap(result, &irmap, &analyzer, "f").is_empty());
+
+    // the index is 64 bits, so it may be past the guard region
+    let (cfg, irmap) = parse_ir(&text.replace("r1:32 <- mov r6:32", "r1:64 <- mov r6:64")).unwrap();
+    let result = run_worklist(&cfg, &irmap, &analyzer);
+    assert_eq!(check_heap(result, &irmap, &analyzer, "f").len(), 1);
+}
@@ -1,11 +1,9 @@
 use crate::utils::lifter::{IRMap, MemArg, MemArgs, Stmt, ValSize, Value};
+use yaxpeax_x86::long_mode::Opcode;
 
 pub fn is_rsp(v: &Value) -> bool {
     match v {
         Value::Reg(4, ValSize::Size64) => return true,
-        Value::Reg(4, ValSize::Size32)
-        | Value::Reg(4, ValSize::Size16)
-        | Value::Reg(4, ValSize::Size8) => panic!("Illegal RSP access"),
         _ => return false,
     }
 }
@@ -13,13 +11,21 @@ pub fn is_rsp(v: &Value) -> bool {
 pub fn is_rbp(v: &Value) -> bool {
     match v {
         Value::Reg(5, ValSize::Size64) => return true,
-        Value::Reg(5, ValSize::Size32)
-        | Value::Reg(5, ValSize::Size16)
-        | Value::Reg(5, ValSize::Size8) => panic!("Illegal RBP access"),
         _ => return false,
     }
 }
 
+// esp, ebp and narrower (or ah/ch, which share their register numbers)
+pub fn is_partial_frame_reg(v: &Value) -> bool {
+    match v {
+        Value::Reg(4, size) | Value::Reg(5, size) => match size {
+            ValSize::Size32 | ValSize::Size16 | ValSize::Size8 => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
 pub fn is_zf(v: &Value) -> bool {
     match v {
         Value::Reg(16, _) => return true,
@@ -28,19 +34,17 @@ pub fn is_zf(v: &Value) -> bool {
 }
 
 pub fn is_irrelevant_reg(v: &Value) -> bool {
-    if let Value::Reg(_, ValSize::SizeOther) = v {
-        return true;
+    match v {
+        Value::Reg(_, size) => *size == ValSize::SizeOther || size.is_vector(),
+        _ => false,
     }
-    false
 }
 
+// Addresses formed from esp count too; the stack checker rejects them.
+// xmm4 as a gather index does not.
 pub fn memarg_is_stack(memarg: &MemArg) -> bool {
-    if let MemArg::Reg(4, regsize) = memarg {
-        if let ValSize::Size64 = regsize {
-            return true;
-        } else {
-            panic!("Non 64 bit version of rsp being used")
-        };
+    if let MemArg::Reg(4, size) = memarg {
+        return !size.is_vector();
     }
     return false;
 }
@@ -62,18 +66,17 @@ pub fn is_stack_access(v: &Value) -> bool {
                     || memarg_is_stack(memarg2)
                     || memarg_is_stack(memarg3)
             }
+            MemArgs::MemScaleDisp(memarg1, memarg2, _scale, _disp) => {
+                return memarg_is_stack(memarg1) || memarg_is_stack(memarg2)
+            }
         }
     }
     false
 }
 
 pub fn memarg_is_bp(memarg: &MemArg) -> bool {
-    if let MemArg::Reg(5, regsize) = memarg {
-        if let ValSize::Size64 = regsize {
-            return true;
-        } else {
-            panic!("Non 64 bit version of rbp being used")
-        };
+    if let MemArg::Reg(5, size) = memarg {
+        return !size.is_vector();
     }
     return false;
 }
@@ -91,17 +94,23 @@ pub fn is_bp_access(v: &Value) -> bool {
             MemArgs::MemScale(memarg1, memarg2, memarg3) => {
                 return memarg_is_bp(memarg1) || memarg_is_bp(memarg2) || memarg_is_bp(memarg3)
             }
+            MemArgs::MemScaleDisp(memarg1, memarg2, _scale, _disp) => {
+                return memarg_is_bp(memarg1) || memarg_is_bp(memarg2)
+            }
         }
     }
     false
 }
 
-pub fn extract_stack_offset(memargs: &MemArgs) -> i64 {
+// The constant offset of a stack access, or None if it is indexed by a
+// register or its base is not a 64-bit register.
+pub fn extract_stack_offset(memargs: &MemArgs) -> Option<i64> {
     match memargs {
-        MemArgs::Mem1Arg(_memarg) => 0,
-        MemArgs::Mem2Args(_memarg1, memarg2) => get_imm_mem_offset(memarg2),
-        MemArgs::Mem3Args(_memarg1, _memarg2, _memarg3)
-        | MemArgs::MemScale(_memarg1, _memarg2, _memarg3) => panic!("extract_stack_offset failed"),
+        MemArgs::Mem1Arg(MemArg::Reg(_, ValSize::Size64)) => Some(0),
+        MemArgs::Mem2Args(MemArg::Reg(_, ValSize::Size64), MemArg::Imm(_, _, offset)) => {
+            Some(*offset)
+        }
+        _ => None,
     }
 }
 
@@ -158,3 +167,19 @@ pub fn has_indirect_jumps(irmap: &IRMap) -> bool {
     }
     false
 }
+
+/// Opcodes that the lifter did not recognize and lifted with `generic_clear`,
+/// one entry per occurrence.
+pub fn get_unknown_opcodes(irmap: &IRMap) -> Vec<Opcode> {
+    let mut opcodes = vec![];
+    for (_block_addr, ir_block) in irmap {
+        for (_addr, ir_stmts) in ir_block {
+            for ir_stmt in ir_stmts {
+                if let Stmt::UnknownOpcode(opcode) = ir_stmt {
+                    opcodes.push(*opcode);
+                }
+            }
+        }
+    }
+    opcodes
+}
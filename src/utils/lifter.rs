@@ -20,6 +20,9 @@ pub enum ValSize {
     Size16,
     Size32,
     Size64,
+    Size128,
+    Size256,
+    Size512,
     SizeOther,
 }
 
@@ -30,9 +33,21 @@ impl ValSize {
             ValSize::Size16 => 16,
             ValSize::Size32 => 32,
             ValSize::Size64 => 64,
+            ValSize::Size128 => 128,
+            ValSize::Size256 => 256,
+            ValSize::Size512 => 512,
             ValSize::SizeOther => 64, //panic!("unknown size? {:?}")
         }
     }
+
+    // xmm/ymm/zmm registers share their numbers with the general purpose
+    // registers, so registers of these sizes are never tracked.
+    pub fn is_vector(&self) -> bool {
+        match self {
+            ValSize::Size128 | ValSize::Size256 | ValSize::Size512 => true,
+            _ => false,
+        }
+    }
 }
 
 pub fn valsize(num: u32) -> ValSize {
@@ -41,6 +56,9 @@ pub fn valsize(num: u32) -> ValSize {
         16 => ValSize::Size16,
         32 => ValSize::Size32,
         64 => ValSize::Size64,
+        128 => ValSize::Size128,
+        256 => ValSize::Size256,
+        512 => ValSize::Size512,
         _ => unimplemented!("{:?}", num),
     }
 }
@@ -51,10 +69,11 @@ pub fn mk_value_i64(num: i64) -> Value {
 
 #[derive(Debug, Clone)]
 pub enum MemArgs {
-    Mem1Arg(MemArg),                  // [arg]
-    Mem2Args(MemArg, MemArg),         // [arg1 + arg2]
-    Mem3Args(MemArg, MemArg, MemArg), // [arg1 + arg2 + arg3]
-    MemScale(MemArg, MemArg, MemArg), // [arg1 + arg2 * arg3]
+    Mem1Arg(MemArg),                              // [arg]
+    Mem2Args(MemArg, MemArg),                     // [arg1 + arg2]
+    Mem3Args(MemArg, MemArg, MemArg),             // [arg1 + arg2 + arg3]
+    MemScale(MemArg, MemArg, MemArg),             // [arg1 + arg2 * arg3]
+    MemScaleDisp(MemArg, MemArg, MemArg, MemArg), // [arg1 + arg2 * arg3 + arg4]
 }
 #[derive(Debug, Clone)]
 pub enum MemArg {
@@ -79,6 +98,7 @@ pub enum Stmt {
     Branch(yaxpeax_x86::long_mode::Opcode, Value), // br branch-type v
     Call(Value),                                   // call v
     ProbeStack(u64),                               // probestack
+    UnknownOpcode(yaxpeax_x86::long_mode::Opcode), // lifted by `generic_clear`
 }
 
 impl Stmt {
@@ -98,6 +118,7 @@ pub enum Binopcode {
     Rol,
     Cmp,
     Shl,
+    Shr,
     And,
     Add,
     Sub,
@@ -112,7 +133,10 @@ fn get_reg_size(reg: yaxpeax_x86::long_mode::RegSpec) -> ValSize {
         register_class::RB => ValSize::Size8,
         register_class::RIP => panic!("Write to RIP: {:?}", reg.class()),
         register_class::EIP => panic!("Write to EIP: {:?}", reg.class()),
-        register_class::X | register_class::Y | register_class::Z => ValSize::SizeOther,
+        register_class::X => ValSize::Size128,
+        register_class::Y => ValSize::Size256,
+        register_class::Z => ValSize::Size512,
+        register_class::K => ValSize::SizeOther,
         _ => panic!("Unknown register bank: {:?}", reg.class()),
     };
     return size;
@@ -129,12 +153,38 @@ fn convert_memarg_reg(reg: yaxpeax_x86::long_mode::RegSpec) -> MemArg {
         register_class::D => ValSize::Size32,
         register_class::W => ValSize::Size16,
         register_class::B => ValSize::Size8,
+        // the index of a gather or scatter
+        register_class::X | register_class::Y | register_class::Z => get_reg_size(reg),
         _ => panic!("Unknown register bank: {:?}", reg.class()),
     };
     MemArg::Reg(reg.num(), size)
 }
 
+// A VSIB operand, which addresses one element for each lane of a vector
+// index.
+fn has_vector_index(op: &yaxpeax_x86::long_mode::Operand) -> bool {
+    match op {
+        Operand::RegIndexBase(_, index)
+        | Operand::RegIndexBaseDisp(_, index, _)
+        | Operand::RegScale(index, _)
+        | Operand::RegScaleDisp(index, _, _)
+        | Operand::RegIndexBaseScale(_, index, _)
+        | Operand::RegIndexBaseScaleDisp(_, index, _, _) => match index.class() {
+            register_class::X | register_class::Y | register_class::Z => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
 fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Value {
+    // the checkers can't bound a vector of addresses, so a gather or scatter
+    // is lifted at SizeOther, which they reject
+    let memsize = if has_vector_index(&op) {
+        ValSize::SizeOther
+    } else {
+        memsize
+    };
     match op {
         Operand::ImmediateI8(imm) => Value::Imm(ImmType::Signed, ValSize::Size8, imm as i64),
         Operand::ImmediateU8(imm) => Value::Imm(ImmType::Unsigned, ValSize::Size8, imm as i64),
@@ -145,6 +195,8 @@ fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Val
         Operand::ImmediateU64(imm) => Value::Imm(ImmType::Unsigned, ValSize::Size64, imm as i64),
         Operand::ImmediateI64(imm) => Value::Imm(ImmType::Signed, ValSize::Size64, imm as i64),
         Operand::Register(reg) => convert_reg(reg),
+        // AVX-512 destination with a write mask
+        Operand::RegisterMaskMerge(reg, _, _) => convert_reg(reg),
         //u32 and u64 are address sizes
         Operand::DisplacementU32(imm) => Value::Mem(
             memsize,
@@ -176,8 +228,22 @@ fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Val
                 MemArg::Imm(ImmType::Signed, ValSize::Size32, imm as i64),
             ),
         ), //mem[reg1 + reg2 + c]
-        Operand::RegScale(_, _) => panic!("Memory operations with scaling prohibited"), // mem[reg * c]
-        Operand::RegScaleDisp(_, _, _) => panic!("Memory operations with scaling prohibited"), //mem[reg*c1 + c2]
+        Operand::RegScale(reg, scale) => Value::Mem(
+            memsize,
+            MemArgs::MemScale(
+                MemArg::Imm(ImmType::Signed, ValSize::Size32, 0),
+                convert_memarg_reg(reg),
+                MemArg::Imm(ImmType::Signed, ValSize::Size32, scale as i64),
+            ),
+        ), // mem[reg * c]
+        Operand::RegScaleDisp(reg, scale, imm) => Value::Mem(
+            memsize,
+            MemArgs::MemScale(
+                MemArg::Imm(ImmType::Signed, ValSize::Size32, imm as i64),
+                convert_memarg_reg(reg),
+                MemArg::Imm(ImmType::Signed, ValSize::Size32, scale as i64),
+            ),
+        ), //mem[reg*c1 + c2]
         Operand::RegIndexBaseScale(reg1, reg2, scale) =>
         //mem[reg1 + reg2*c]
         {
@@ -198,15 +264,26 @@ fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Val
             }
         }
         Operand::RegIndexBaseScaleDisp(reg1, reg2, scale, imm) => {
-            assert_eq!(scale, 1);
-            Value::Mem(
-                memsize,
-                MemArgs::Mem3Args(
-                    convert_memarg_reg(reg1),
-                    convert_memarg_reg(reg2),
-                    MemArg::Imm(ImmType::Signed, ValSize::Size32, imm as i64),
-                ),
-            )
+            if scale == 1 {
+                Value::Mem(
+                    memsize,
+                    MemArgs::Mem3Args(
+                        convert_memarg_reg(reg1),
+                        convert_memarg_reg(reg2),
+                        MemArg::Imm(ImmType::Signed, ValSize::Size32, imm as i64),
+                    ),
+                )
+            } else {
+                Value::Mem(
+                    memsize,
+                    MemArgs::MemScaleDisp(
+                        convert_memarg_reg(reg1),
+                        convert_memarg_reg(reg2),
+                        MemArg::Imm(ImmType::Signed, ValSize::Size32, scale as i64),
+                        MemArg::Imm(ImmType::Signed, ValSize::Size32, imm as i64),
+                    ),
+                )
+            }
         } //mem[reg1 + reg2*c1 + c2]
         Operand::Nothing => panic!("Nothing Operand?"),
         op => {
@@ -216,23 +293,24 @@ fn convert_operand(op: yaxpeax_x86::long_mode::Operand, memsize: ValSize) -> Val
 }
 
 fn get_sources(instr: &yaxpeax_x86::long_mode::Instruction) -> Vec<Value> {
+    let memsize = get_mem_size(instr).unwrap_or(ValSize::SizeOther);
     match instr.operand_count() {
         0 => vec![],
-        1 => vec![convert_operand(instr.operand(0), ValSize::Size32)],
+        1 => vec![convert_operand(instr.operand(0), memsize)],
         2 => vec![
-            convert_operand(instr.operand(0), ValSize::Size32),
-            convert_operand(instr.operand(1), ValSize::Size32),
+            convert_operand(instr.operand(0), memsize),
+            convert_operand(instr.operand(1), memsize),
         ],
         3 => vec![
-            convert_operand(instr.operand(0), ValSize::Size32),
-            convert_operand(instr.operand(1), ValSize::Size32),
-            convert_operand(instr.operand(2), ValSize::Size32),
+            convert_operand(instr.operand(0), memsize),
+            convert_operand(instr.operand(1), memsize),
+            convert_operand(instr.operand(2), memsize),
         ],
         4 => vec![
-            convert_operand(instr.operand(0), ValSize::Size32),
-            convert_operand(instr.operand(1), ValSize::Size32),
-            convert_operand(instr.operand(2), ValSize::Size32),
-            convert_operand(instr.operand(3), ValSize::Size32),
+            convert_operand(instr.operand(0), memsize),
+            convert_operand(instr.operand(1), memsize),
+            convert_operand(instr.operand(2), memsize),
+            convert_operand(instr.operand(3), memsize),
         ],
         _ => panic!("Too many arguments?"),
     }
@@ -248,7 +326,10 @@ fn clear_dst(instr: &yaxpeax_x86::long_mode::Instruction) -> Vec<Stmt> {
     let mut stmts: Vec<Stmt> = Vec::new();
 
     stmts.push(Stmt::Clear(
-        convert_operand(instr.operand(0), ValSize::Size8),
+        convert_operand(
+            instr.operand(0),
+            get_mem_size(instr).unwrap_or(ValSize::SizeOther),
+        ),
         srcs.clone(),
     ));
     if writes_to_zf {
@@ -257,19 +338,61 @@ fn clear_dst(instr: &yaxpeax_x86::long_mode::Instruction) -> Vec<Stmt> {
     stmts
 }
 
-// Generic handling for unknown opcodes.
+fn get_tracked_reg_size(reg: yaxpeax_x86::long_mode::RegSpec) -> Option<ValSize> {
+    match reg.class() {
+        register_class::Q
+        | register_class::D
+        | register_class::W
+        | register_class::B
+        | register_class::RB
+        | register_class::X
+        | register_class::Y
+        | register_class::Z => Some(get_reg_size(reg)),
+        _ => None,
+    }
+}
+
+// Generic handling for unknown opcodes. This is sound but imprecise: every
+// register (and zf) the instruction writes is cleared, and every memory
+// operand is treated as a write of an unknown value, so the heap checker
+// still has to validate the access and any stack slot it may alias is
+// forgotten.
 fn generic_clear(instr: &yaxpeax_x86::long_mode::Instruction) -> Vec<Stmt> {
     let uses_vec = <AMD64 as ValueLocations>::decompose(instr);
     let writes_to_zf = uses_vec.iter().any(|(loc, dir)| match (loc, dir) {
         (Some(Location::ZF), Direction::Write) => true,
         _ => false,
     });
-    let mut stmts = vec![];
+    let mut stmts = vec![Stmt::UnknownOpcode(instr.opcode())];
 
     for (loc, dir) in uses_vec {
         match (loc, dir) {
             (Some(Location::Register(reg)), Direction::Write) => {
-                stmts.push(Stmt::Clear(convert_reg(reg), vec![]));
+                if let Some(size) = get_tracked_reg_size(reg) {
+                    stmts.push(Stmt::Clear(Value::Reg(reg.num(), size), vec![]));
+                }
+            }
+            _ => {}
+        }
+    }
+    for i in 0..instr.operand_count() {
+        match instr.operand(i) {
+            Operand::DisplacementU32(_)
+            | Operand::DisplacementU64(_)
+            | Operand::RegDeref(_)
+            | Operand::RegDisp(_, _)
+            | Operand::RegIndexBase(_, _)
+            | Operand::RegIndexBaseDisp(_, _, _)
+            | Operand::RegScale(_, _)
+            | Operand::RegScaleDisp(_, _, _)
+            | Operand::RegIndexBaseScale(_, _, _)
+            | Operand::RegIndexBaseScaleDisp(_, _, _, _) => {
+                let memsize = get_mem_size(instr).unwrap_or(ValSize::SizeOther);
+                match convert_operand(instr.operand(i), memsize) {
+                    // rip-relative operands only address constant data
+                    Value::RIPConst => {}
+                    mem => stmts.push(Stmt::Clear(mem, vec![])),
+                }
             }
             _ => {}
         }
@@ -281,13 +404,101 @@ fn generic_clear(instr: &yaxpeax_x86::long_mode::Instruction) -> Vec<Stmt> {
     stmts
 }
 
+// The width of the instruction's memory operand, as decoded. If the decoder
+// doesn't know it, a vector instruction is taken to access as many bytes as
+// its widest vector register, which is never less than it does access.
+// Otherwise the width is unknown and callers lift the operand at SizeOther,
+// which the checkers reject.
+fn get_mem_size(instr: &yaxpeax_x86::long_mode::Instruction) -> Option<ValSize> {
+    if let Some(bytes) = instr.mem_size().and_then(|size| size.bytes_size()) {
+        if bytes.is_power_of_two() && bytes <= 64 {
+            return Some(valsize(bytes as u32 * 8));
+        }
+    }
+    (0..instr.operand_count())
+        .filter_map(|i| vector_reg_size(instr.operand(i)))
+        .max_by_key(|size| size.to_u32())
+}
+
+fn vector_reg_size(op: yaxpeax_x86::long_mode::Operand) -> Option<ValSize> {
+    match op {
+        Operand::Register(reg) | Operand::RegisterMaskMerge(reg, _, _) => match reg.class() {
+            register_class::X | register_class::Y | register_class::Z => Some(get_reg_size(reg)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn has_vector_operand(instr: &yaxpeax_x86::long_mode::Instruction) -> bool {
+    (0..instr.operand_count()).any(|i| match instr.operand(i) {
+        Operand::Register(reg) | Operand::RegisterMaskMerge(reg, _, _) => match reg.class() {
+            register_class::X | register_class::Y | register_class::Z | register_class::K => true,
+            _ => false,
+        },
+        _ => false,
+    })
+}
+
+// SSE, AVX and AVX-512 instructions not listed in `lift`, which the data-flow
+// decomposition doesn't know. Operand 0 is the destination (for compares
+// that only set flags it is a vector register, so clearing it is a no-op)
+// and the memory operand, if any, is checked at its full width. Besides
+// vector and mask registers, which are not tracked, the only other state
+// they write is the flags and, for the string compares, rcx.
+fn vector_op(instr: &yaxpeax_x86::long_mode::Instruction) -> Vec<Stmt> {
+    let srcs = get_sources(instr);
+    let memsize = get_mem_size(instr).unwrap_or(ValSize::SizeOther);
+    let mut stmts = vec![Stmt::Clear(
+        convert_operand(instr.operand(0), memsize),
+        srcs.clone(),
+    )];
+    match instr.opcode() {
+        Opcode::PCMPESTRI | Opcode::PCMPISTRI | Opcode::VPCMPESTRI | Opcode::VPCMPISTRI => {
+            stmts.push(Stmt::Clear(Value::Reg(1, ValSize::Size64), srcs.clone()));
+            stmts.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), srcs));
+        }
+        Opcode::PCMPESTRM
+        | Opcode::PCMPISTRM
+        | Opcode::VPCMPESTRM
+        | Opcode::VPCMPISTRM
+        | Opcode::COMISS
+        | Opcode::COMISD
+        | Opcode::VCOMISS
+        | Opcode::VCOMISD
+        | Opcode::VUCOMISS
+        | Opcode::VUCOMISD
+        | Opcode::VPTEST
+        | Opcode::VTESTPS
+        | Opcode::VTESTPD
+        | Opcode::KORTESTB
+        | Opcode::KORTESTW
+        | Opcode::KORTESTD
+        | Opcode::KORTESTQ
+        | Opcode::KTESTB
+        | Opcode::KTESTW
+        | Opcode::KTESTD
+        | Opcode::KTESTQ => stmts.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), srcs)),
+        // store the bytes selected by the mask to [rdi]
+        Opcode::MASKMOVDQU | Opcode::VMASKMOVDQU => stmts.push(Stmt::Clear(
+            Value::Mem(
+                ValSize::Size128,
+                MemArgs::Mem1Arg(MemArg::Reg(7, ValSize::Size64)),
+            ),
+            srcs,
+        )),
+        _ => {}
+    }
+    stmts
+}
+
 fn get_operand_size(op: yaxpeax_x86::long_mode::Operand) -> Option<ValSize> {
     match op {
         Operand::ImmediateI8(_) | Operand::ImmediateU8(_) => Some(ValSize::Size8),
         Operand::ImmediateI16(_) | Operand::ImmediateU16(_) => Some(ValSize::Size16),
         Operand::ImmediateU32(_) | Operand::ImmediateI32(_) => Some(ValSize::Size32),
         Operand::ImmediateU64(_) | Operand::ImmediateI64(_) => Some(ValSize::Size64),
-        Operand::Register(reg) => Some(get_reg_size(reg)),
+        Operand::Register(reg) | Operand::RegisterMaskMerge(reg, _, _) => Some(get_reg_size(reg)),
         //u32 and u64 are address sizes
         Operand::DisplacementU32(_)
         | Operand::DisplacementU64(_)
@@ -306,8 +517,10 @@ fn get_operand_size(op: yaxpeax_x86::long_mode::Operand) -> Option<ValSize> {
     }
 }
 
-fn unop(opcode: Unopcode, instr: &yaxpeax_x86::long_mode::Instruction) -> Stmt {
-    let memsize = match (
+// Without the decoder's width, a memory operand is as wide as the other
+// operand.
+fn operands_size(instr: &yaxpeax_x86::long_mode::Instruction) -> ValSize {
+    match (
         get_operand_size(instr.operand(0)),
         get_operand_size(instr.operand(1)),
     ) {
@@ -315,7 +528,11 @@ fn unop(opcode: Unopcode, instr: &yaxpeax_x86::long_mode::Instruction) -> Stmt {
         (Some(x), None) => x,
         (None, Some(x)) => x,
         (Some(x), Some(_y)) => x,
-    };
+    }
+}
+
+fn unop(opcode: Unopcode, instr: &yaxpeax_x86::long_mode::Instruction) -> Stmt {
+    let memsize = get_mem_size(instr).unwrap_or_else(|| operands_size(instr));
     Stmt::Unop(
         opcode,
         convert_operand(instr.operand(0), memsize),
@@ -324,15 +541,7 @@ fn unop(opcode: Unopcode, instr: &yaxpeax_x86::long_mode::Instruction) -> Stmt {
 }
 
 fn binop(opcode: Binopcode, instr: &yaxpeax_x86::long_mode::Instruction) -> Stmt {
-    let memsize = match (
-        get_operand_size(instr.operand(0)),
-        get_operand_size(instr.operand(1)),
-    ) {
-        (None, None) => panic!("Two Memory Args?"),
-        (Some(x), None) => x,
-        (None, Some(x)) => x,
-        (Some(x), Some(_y)) => x,
-    };
+    let memsize = get_mem_size(instr).unwrap_or_else(|| operands_size(instr));
     // if two operands than dst is src1
     if instr.operand_count() == 2 {
         Stmt::Binop(
@@ -378,22 +587,140 @@ fn lea(instr: &yaxpeax_x86::long_mode::Instruction, addr: &u64) -> Vec<Stmt> {
             )];
         }
     }
-    match convert_operand(src1, get_operand_size(dst).unwrap()) {
-        Value::Mem(_, memargs) => match memargs {
-            MemArgs::Mem1Arg(arg) => match arg {
-                MemArg::Imm(_, _, _val) => vec![unop(Unopcode::Mov, instr)],
-                _ => clear_dst(instr),
-            },
-            _ => clear_dst(instr),
-        },
+    // lea only computes an address, it never accesses memory, so its operand
+    // is not lifted as a memory access.
+    match convert_operand(src1, ValSize::SizeOther) {
+        Value::Mem(_, MemArgs::Mem1Arg(MemArg::Imm(_, _, val))) => vec![Stmt::Unop(
+            Unopcode::Mov,
+            convert_operand(dst, ValSize::SizeOther),
+            Value::Imm(ImmType::Signed, ValSize::Size64, val),
+        )],
+        // reg + c, except into rsp, where the stack analysis only trusts
+        // explicit adds and subs
+        Value::Mem(_, MemArgs::Mem2Args(MemArg::Reg(regnum, size), MemArg::Imm(_, _, val)))
+            if !matches!(dst, Operand::Register(reg) if reg.num() == 4) =>
+        {
+            vec![Stmt::Binop(
+                Binopcode::Add,
+                convert_operand(dst, ValSize::SizeOther),
+                Value::Reg(regnum, size),
+                Value::Imm(ImmType::Signed, ValSize::Size32, val),
+            )]
+        }
+        Value::Mem(_, memargs) => vec![Stmt::Clear(
+            convert_operand(dst, ValSize::SizeOther),
+            address_regs(&memargs),
+        )],
         _ => panic!("Illegal lea"),
     }
 }
 
+fn address_regs(memargs: &MemArgs) -> Vec<Value> {
+    let args = match memargs {
+        MemArgs::Mem1Arg(x) => vec![x],
+        MemArgs::Mem2Args(x, y) => vec![x, y],
+        MemArgs::Mem3Args(x, y, z) | MemArgs::MemScale(x, y, z) => vec![x, y, z],
+        MemArgs::MemScaleDisp(w, x, y, z) => vec![w, x, y, z],
+    };
+    args.into_iter()
+        .filter_map(|arg| match arg {
+            MemArg::Reg(regnum, size) => Some(Value::Reg(*regnum, *size)),
+            MemArg::Imm(_, _, _) => None,
+        })
+        .collect()
+}
+
+// The elements a string instruction accesses through `reg`: the first and,
+// with a rep prefix, the last, at reg + (rcx - 1) * size when the direction
+// flag is clear or reg - (rcx - 1) * size when it is set. The accesses in
+// between lie between the two.
+fn string_extent(reg: u8, size: ValSize, rep: bool, df_may_be_set: bool) -> Vec<Value> {
+    let base = MemArg::Reg(reg, ValSize::Size64);
+    let count = MemArg::Reg(1, ValSize::Size64);
+    let width = (size.to_u32() / 8) as i64;
+    let imm = |v: i64| MemArg::Imm(ImmType::Signed, ValSize::Size64, v);
+    let mut extent = vec![Value::Mem(size, MemArgs::Mem1Arg(base.clone()))];
+    if rep {
+        let last = if width == 1 {
+            MemArgs::Mem3Args(base.clone(), count.clone(), imm(-1))
+        } else {
+            MemArgs::MemScaleDisp(base.clone(), count.clone(), imm(width), imm(-width))
+        };
+        extent.push(Value::Mem(size, last));
+        if df_may_be_set {
+            extent.push(Value::Mem(
+                size,
+                MemArgs::MemScaleDisp(base, count, imm(-width), imm(width)),
+            ));
+        }
+    }
+    extent
+}
+
+// movs, stos, lods, scas and cmps, which access memory at rdi and rsi and,
+// with a rep prefix, repeat rcx times.
+fn string_op(instr: &yaxpeax_x86::long_mode::Instruction, df_may_be_set: bool) -> Vec<Stmt> {
+    let reg_size = (0..instr.operand_count()).find_map(|i| match instr.operand(i) {
+        Operand::Register(reg) => Some(get_reg_size(reg)),
+        _ => None,
+    });
+    // a wider element is never less conservative
+    let size = get_mem_size(instr).or(reg_size).unwrap_or(ValSize::Size64);
+    let rep = instr.prefixes.rep() || instr.prefixes.repnz();
+    let rdi = string_extent(7, size, rep, df_may_be_set);
+    let rsi = string_extent(6, size, rep, df_may_be_set);
+    let rax = Value::Reg(0, size);
+    let zf = Value::Reg(16, ValSize::Size8);
+
+    let mut stmts = vec![];
+    let (uses_rdi, uses_rsi) = match instr.opcode() {
+        Opcode::MOVS => {
+            for (dst, src) in rdi.into_iter().zip(rsi) {
+                stmts.push(Stmt::Clear(dst, vec![src]));
+            }
+            (true, true)
+        }
+        Opcode::STOS => {
+            for dst in rdi {
+                stmts.push(Stmt::Clear(dst, vec![rax.clone()]));
+            }
+            (true, false)
+        }
+        Opcode::LODS => {
+            stmts.push(Stmt::Clear(rax, rsi));
+            (false, true)
+        }
+        Opcode::SCAS => {
+            let mut srcs = rdi;
+            srcs.push(rax);
+            stmts.push(Stmt::Clear(zf, srcs));
+            (true, false)
+        }
+        Opcode::CMPS => {
+            let mut srcs = rsi;
+            srcs.extend(rdi);
+            stmts.push(Stmt::Clear(zf, srcs));
+            (true, true)
+        }
+        _ => panic!("Not a string instruction: {:?}", instr.opcode()),
+    };
+    if uses_rdi {
+        stmts.push(Stmt::Clear(Value::Reg(7, ValSize::Size64), vec![]));
+    }
+    if uses_rsi {
+        stmts.push(Stmt::Clear(Value::Reg(6, ValSize::Size64), vec![]));
+    }
+    if rep {
+        stmts.push(Stmt::Clear(Value::Reg(1, ValSize::Size64), vec![]));
+    }
+    stmts
+}
+
 pub fn lift(
     instr: &yaxpeax_x86::long_mode::Instruction,
     addr: &u64,
     metadata: &LucetMetadata,
+    df_may_be_set: bool,
 ) -> Vec<Stmt> {
     log::debug!("lift: addr 0x{:x} instr {:?}", addr, instr);
     let mut instrs = Vec::new();
@@ -443,6 +770,13 @@ pub fn lift(
                 get_sources(instr),
             ))
         }
+        Opcode::SHR => {
+            instrs.push(binop(Binopcode::Shr, instr));
+            instrs.push(Stmt::Clear(
+                Value::Reg(16, ValSize::Size8),
+                get_sources(instr),
+            ))
+        }
 
         Opcode::CMOVNB => {
             // Part of Spectre mitigation. Assume CMOV never happens (if it does, we just trap).
@@ -511,7 +845,12 @@ pub fn lift(
             ))
         }
 
+        // the direction flag is accounted for by `sets_direction_flag`
         Opcode::NOP | Opcode::FILD | Opcode::STD | Opcode::CLD | Opcode::STI => (),
+
+        Opcode::MOVS | Opcode::STOS | Opcode::LODS | Opcode::SCAS | Opcode::CMPS => {
+            instrs.extend(string_op(instr, df_may_be_set))
+        }
         Opcode::IDIV | Opcode::DIV => {
             // instrs.push(Stmt::Clear(Value::Reg(16, ValSize::Size8), vec![]));
             instrs.push(Stmt::Clear(Value::Reg(0, ValSize::Size64), vec![])); // clear RAX
@@ -547,7 +886,6 @@ pub fn lift(
         }
 
         Opcode::OR
-        | Opcode::SHR
         | Opcode::RCL
         | Opcode::RCR
         | Opcode::ROL
@@ -725,7 +1063,19 @@ pub fn lift(
         | Opcode::ANDPD
         | Opcode::ORPD => instrs.extend(clear_dst(instr)),
 
-        _ => unimplemented!(),/*instrs.extend(generic_clear(instr)),*/
+        // only the upper halves of vector registers, which are not tracked
+        Opcode::VZEROUPPER | Opcode::VZEROALL => (),
+
+        _ if has_vector_operand(instr) => instrs.extend(vector_op(instr)),
+
+        _ => {
+            log::debug!(
+                "lift: unknown opcode {:?} at 0x{:x}, clearing its destinations",
+                instr.opcode(),
+                addr
+            );
+            instrs.extend(generic_clear(instr))
+        }
     };
     instrs
 }
@@ -742,7 +1092,7 @@ fn is_probestack(
         let op = convert_operand(instr.operand(0), ValSize::SizeOther);
         if let Value::Imm(_, _, offset) = op {
             // 5 = size of call instruction
-            if 5 + offset + (*addr as i64) == metadata.lucet_probestack as i64 {
+            if Some((5 + offset + (*addr as i64)) as u64) == metadata.lucet_probestack {
                 return true;
             }
         }
@@ -765,6 +1115,25 @@ fn extract_probestack_arg(instr: &yaxpeax_x86::long_mode::Instruction) -> Option
     None
 }
 
+// The ABI has the direction flag clear on entry and after calls, so string
+// instructions only run downwards in a function that sets it.
+fn sets_direction_flag(program: &ModuleData, cfg: &VW_CFG) -> bool {
+    for block_addr in cfg.graph.nodes() {
+        let block = cfg.get_block(block_addr);
+        let mut iter = program.instructions_spanning(
+            <AMD64 as Arch>::Decoder::default(),
+            block.start,
+            block.end,
+        );
+        while let Some((_addr, instr)) = iter.next() {
+            if instr.opcode() == Opcode::STD {
+                return true;
+            }
+        }
+    }
+    false
+}
+
 fn check_probestack_suffix(instr: &yaxpeax_x86::long_mode::Instruction) -> bool {
     if let Opcode::SUB = instr.opcode() {
         if let Value::Reg(4, ValSize::Size64) =
@@ -786,6 +1155,7 @@ fn check_probestack_suffix(instr: &yaxpeax_x86::long_mode::Instruction) -> bool
 pub fn lift_cfg(program: &ModuleData, cfg: &VW_CFG, metadata: &LucetMetadata) -> IRMap {
     let mut irmap = IRMap::new();
     let g = &cfg.graph;
+    let df_may_be_set = sets_direction_flag(program, cfg);
     for block_addr in g.nodes() {
         let mut block_ir: Vec<(u64, Vec<Stmt>)> = Vec::new();
         let block = cfg.get_block(block_addr);
@@ -816,7 +1186,7 @@ pub fn lift_cfg(program: &ModuleData, cfg: &VW_CFG, metadata: &LucetMetadata) ->
                     None => panic!("probestack broken"),
                 }
             }
-            let ir = (addr, lift(instr, &addr, metadata));
+            let ir = (addr, lift(instr, &addr, metadata, df_may_be_set));
             block_ir.push(ir);
             x = extract_probestack_arg(instr);
             if instr.opcode() == Opcode::JMP {
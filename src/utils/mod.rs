@@ -1,4 +1,9 @@
+pub mod cwasm;
+pub mod dot;
+pub mod explain;
+pub mod ir_text;
 pub mod ir_utils;
 pub mod lifter;
+pub mod relocs;
 pub mod testing;
 pub mod utils;
@@ -0,0 +1,250 @@
+//! Unlinked relocatable objects (`.o`).
+//!
+//! Before linking, the immediates that refer to runtime symbols such as
+//! `guest_table_0`, and the targets of calls to `lucet_probestack`, are zero
+//! in the object's code, with a relocation saying what goes there. To check
+//! such code, `.text` is linked for analysis: it is placed at address 0, every
+//! other section and undefined symbol is given a made-up address after it, and
+//! the `.text` relocations are applied. Lifting and the analyses then see the
+//! same immediates they would in a linked binary, with runtime symbols at
+//! their made-up addresses.
+
+use object::{
+    Object, ObjectSection, ObjectSymbol, RelocationKind, RelocationTarget, SymbolSection,
+};
+use std::collections::HashMap;
+use yaxpeax_core::memory::repr::process::{ELFSection, ELFSymbol, ModuleData, ModuleInfo, Segment};
+
+pub const ET_REL: u16 = 1;
+
+pub fn is_relocatable(data: &[u8]) -> bool {
+    data.len() >= 18
+        && &data[0..4] == b"\x7fELF"
+        && u16::from_le_bytes([data[16], data[17]]) == ET_REL
+}
+
+pub struct LinkedText {
+    /// `.text`, with its relocations applied.
+    pub text: Vec<u8>,
+    /// The address given to each named symbol.
+    pub symbol_addrs: HashMap<String, u64>,
+}
+
+pub fn align_up(addr: u64, align: u64) -> u64 {
+    (addr + align - 1) / align * align
+}
+
+/// Writes the low `size` bytes of `value` at `offset`.
+pub fn write_reloc(code: &mut [u8], offset: usize, size: usize, value: i64) -> Result<(), String> {
+    let end = offset + size;
+    if end > code.len() {
+        return Err(format!(
+            "relocation past the end of the code at 0x{:x}",
+            offset
+        ));
+    }
+    code[offset..end].copy_from_slice(&value.to_le_bytes()[..size]);
+    Ok(())
+}
+
+pub fn link_text(data: &[u8]) -> Result<LinkedText, String> {
+    let file = object::File::parse(data).map_err(|err| err.to_string())?;
+    let text = file
+        .section_by_name(".text")
+        .ok_or_else(|| "no .text section".to_string())?;
+
+    let mut section_addrs = HashMap::new();
+    let mut next_addr = align_up(text.size(), 0x1000);
+    for section in file.sections() {
+        if section.index() == text.index() {
+            section_addrs.insert(section.index(), 0);
+            continue;
+        }
+        if let Ok(name) = section.name() {
+            if name.starts_with(".text.") {
+                return Err(format!("code outside .text in {}", name));
+            }
+        }
+        section_addrs.insert(section.index(), next_addr);
+        next_addr = align_up(next_addr + section.size(), 0x1000);
+    }
+
+    let mut addrs_by_index = HashMap::new();
+    let mut symbol_addrs = HashMap::new();
+    for symbol in file.symbols() {
+        let addr = match symbol.section() {
+            SymbolSection::Section(index) => section_addrs[&index] + symbol.address(),
+            SymbolSection::Absolute => symbol.address(),
+            _ => {
+                next_addr += 16;
+                next_addr - 16
+            }
+        };
+        addrs_by_index.insert(symbol.index(), addr);
+        match symbol.name() {
+            Ok(name) if !name.is_empty() => {
+                symbol_addrs.insert(name.to_string(), addr);
+            }
+            _ => (),
+        }
+    }
+
+    let mut text_data = text.data().map_err(|err| err.to_string())?.to_vec();
+    for (offset, reloc) in text.relocations() {
+        if reloc.has_implicit_addend() {
+            return Err(format!("implicit addend at .text+0x{:x}", offset));
+        }
+        let target = match reloc.target() {
+            RelocationTarget::Symbol(index) => addrs_by_index.get(&index),
+            RelocationTarget::Section(index) => section_addrs.get(&index),
+        }
+        .ok_or_else(|| format!("unknown relocation target at .text+0x{:x}", offset))?;
+        let value = match reloc.kind() {
+            RelocationKind::Absolute => *target as i64 + reloc.addend(),
+            RelocationKind::Relative | RelocationKind::PltRelative => {
+                *target as i64 + reloc.addend() - offset as i64
+            }
+            kind => {
+                return Err(format!(
+                    "unsupported relocation {:?} at .text+0x{:x}",
+                    kind, offset
+                ))
+            }
+        };
+        write_reloc(
+            &mut text_data,
+            offset as usize,
+            reloc.size() as usize / 8,
+            value,
+        )?;
+    }
+
+    Ok(LinkedText {
+        text: text_data,
+        symbol_addrs,
+    })
+}
+
+/// Builds a module holding the linked `.text` of a relocatable object, with
+/// a symbol for every named symbol, including the runtime symbols it
+/// imports, so it can be checked like a linked binary.
+pub fn relocatable_module(name: &str, data: &[u8]) -> Result<ModuleData, String> {
+    let linked = link_text(data)?;
+    let mut symbols: Vec<ELFSymbol> = linked
+        .symbol_addrs
+        .into_iter()
+        .map(|(name, addr)| ELFSymbol { name, addr })
+        .collect();
+    symbols.sort_by_key(|sym| sym.addr);
+    let text = ELFSection {
+        name: ".text".to_owned(),
+        start: 0,
+        size: linked.text.len() as u64,
+    };
+    let header = yaxpeax_core::goblin::elf::header::Header {
+        e_ident: [
+            0x7f, 0x45, 0x4c, 0x46, 0x02, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ],
+        e_type: ET_REL,
+        e_machine: 0x003e,
+        e_version: 0x00000001,
+        e_entry: 0,
+        e_phoff: 0,
+        e_shoff: 0,
+        e_flags: 0,
+        e_ehsize: 0,
+        e_phentsize: 0,
+        e_phnum: 0,
+        e_shentsize: 0,
+        e_shnum: 0,
+        e_shstrndx: 0,
+    };
+    let module_info = ModuleInfo::ELF(
+        yaxpeax_core::memory::repr::process::ISAHint::Hint(yaxpeax_core::arch::ISA::x86_64),
+        header,
+        vec![],
+        vec![text],
+        0,
+        vec![],
+        vec![],
+        vec![],
+        symbols,
+    );
+    Ok(ModuleData {
+        segments: vec![Segment {
+            start: 0,
+            data: linked.text,
+            name: ".text".to_owned(),
+        }],
+        name: name.to_owned(),
+        module_info,
+    })
+}
+
+#[cfg(test)]
+fn test_object(relocs: &[(u64, u32, i64)]) -> Vec<u8> {
+    use crate::utils::testing::elf_object;
+
+    // symbol 1 is an undefined `guest_table_0`
+    let mut symtab = vec![0u8; 24];
+    symtab.extend_from_slice(&1u32.to_le_bytes());
+    symtab.extend_from_slice(&[0x10, 0, 0, 0]);
+    symtab.extend_from_slice(&[0; 16]);
+    let mut rela = vec![];
+    for (offset, r_type, addend) in relocs.iter() {
+        rela.extend_from_slice(&offset.to_le_bytes());
+        rela.extend_from_slice(&((1u64 << 32) | *r_type as u64).to_le_bytes());
+        rela.extend_from_slice(&addend.to_le_bytes());
+    }
+    elf_object(
+        ET_REL,
+        &[
+            (".text", 1, 0, 0, 0, vec![0; 16]),
+            (".symtab", 2, 3, 1, 24, symtab),
+            (".strtab", 3, 0, 0, 0, b"\0guest_table_0\0".to_vec()),
+            (".rela.text", 4, 2, 1, 24, rela),
+        ],
+    )
+}
+
+#[test]
+fn link_text_test() {
+    // R_X86_64_64 and R_X86_64_PC32
+    let linked = link_text(&test_object(&[(0, 1, 8), (8, 2, -4)])).unwrap();
+    let table = linked.symbol_addrs["guest_table_0"] as i64;
+    assert!(table >= 0x1000);
+    assert_eq!(linked.text[0..8], (table + 8).to_le_bytes());
+    assert_eq!(linked.text[8..12], (table - 4 - 8).to_le_bytes()[..4]);
+    // R_X86_64_GOTPCREL
+    assert_eq!(
+        link_text(&test_object(&[(0, 1, 8), (12, 9, -4)])).err(),
+        Some("unsupported relocation GotRelative at .text+0xc".to_string())
+    );
+}
+
+#[test]
+fn link_text_out_of_range_test() {
+    // a 4-byte R_X86_64_32 two bytes before the end of `.text`
+    assert_eq!(
+        link_text(&test_object(&[(14, 10, 0)])).err(),
+        Some("relocation past the end of the code at 0xe".to_string())
+    );
+}
+
+#[test]
+fn link_text_sections_test() {
+    use crate::utils::testing::elf_object;
+
+    let data = elf_object(
+        ET_REL,
+        &[
+            (".text", 1, 0, 0, 0, vec![0xc3]),
+            (".text.unlikely", 1, 0, 0, 0, vec![0xc3]),
+        ],
+    );
+    assert_eq!(
+        link_text(&data).err(),
+        Some("code outside .text in .text.unlikely".to_string())
+    );
+}
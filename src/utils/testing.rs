@@ -6,72 +6,56 @@ use crate::analyses::stack_analyzer::StackAnalyzer;
 use crate::checkers::call_checker::check_calls;
 use crate::checkers::heap_checker::check_heap;
 use crate::checkers::stack_checker::check_stack;
+use crate::profile::RuntimeProfile;
+use crate::report::ReportVerdict;
 use crate::utils::ir_utils::has_indirect_calls;
 use crate::utils::utils::{fully_resolved_cfg, get_data, get_one_resolved_cfg};
 use crate::utils::utils::{load_metadata, load_program};
+use crate::{verify_module, VerifyConfig};
+use crate::{HeapStrategy, StackStrategy};
 use std::panic;
 use yaxpeax_core::analyses::control_flow::check_cfg_integrity;
 
 fn full_test_helper(path: &str) {
-    let program = load_program(&path);
-    println!("Loading Metadata");
-    let metadata = load_metadata(&path);
-    let (x86_64_data, func_addrs, plt) = get_data(&path, &program);
-    let valid_funcs: Vec<u64> = func_addrs.clone().iter().map(|x| x.0).collect();
-    for (addr, _func_name) in func_addrs {
-        let (cfg, irmap) = fully_resolved_cfg(&program, &x86_64_data.contexts, &metadata, addr);
-        check_cfg_integrity(&cfg.blocks, &cfg.graph);
-        let stack_analyzer = StackAnalyzer {};
-        let stack_result = run_worklist(&cfg, &irmap, &stack_analyzer);
-        let stack_safe = check_stack(stack_result, &irmap, &stack_analyzer);
-        assert!(stack_safe);
-        println!("Checking Heap Safety");
-        let heap_analyzer = HeapAnalyzer {
-            metadata: metadata.clone(),
-        };
-        let heap_result = run_worklist(&cfg, &irmap, &heap_analyzer);
-        let heap_safe = check_heap(heap_result, &irmap, &heap_analyzer);
-        assert!(heap_safe);
-        println!("Checking Call Safety");
-        if has_indirect_calls(&irmap) {
-            let reaching_defs = analyze_reaching_defs(&cfg, &irmap, metadata.clone());
-            let call_analyzer = CallAnalyzer {
-                metadata: metadata.clone(),
-                reaching_defs: reaching_defs.clone(),
-                reaching_analyzer: ReachingDefnAnalyzer {
-                    cfg: cfg.clone(),
-                    irmap: irmap.clone(),
-                },
-                funcs: vec![],
-            };
-            let call_result = run_worklist(&cfg, &irmap, &call_analyzer);
-            let call_safe = check_calls(call_result, &irmap, &call_analyzer, &valid_funcs, &plt);
-            assert!(call_safe);
-        }
+    let data = std::fs::read(path).unwrap();
+    let report = verify_module(&data, &VerifyConfig::default()).unwrap();
+    for function in &report.functions {
+        assert_eq!(
+            function.verdict,
+            ReportVerdict::Safe,
+            "{}: {:?} {:?}",
+            function.name,
+            function.error,
+            function.violations
+        );
     }
     println!("Done!");
 }
 
 fn negative_test_helper(path: &str, func_name: &str) {
-    let program = load_program(&path);
-    let (x86_64_data, func_addrs, plt) = get_data(&path, &program);
+    let program = load_program(&path).unwrap();
+    let (_, func_addrs, plt) = get_data(&program).unwrap();
     let valid_funcs: Vec<u64> = func_addrs.clone().iter().map(|x| x.0).collect();
     println!("Loading Metadata");
-    let metadata = load_metadata(&path);
-    let ((cfg, irmap), x86_64_data) = get_one_resolved_cfg(path, func_name);
+    let metadata = load_metadata(&path, &RuntimeProfile::lucet()).unwrap();
+    let ((cfg, irmap), x86_64_data) = get_one_resolved_cfg(path, func_name).unwrap();
     println!("Analyzing: {:?}", func_name);
     check_cfg_integrity(&cfg.blocks, &cfg.graph);
     println!("Checking Stack Safety");
-    let stack_analyzer = StackAnalyzer {};
+    let stack_analyzer = StackAnalyzer {
+        strategy: StackStrategy::ProbeStack,
+        entrypoint: cfg.entrypoint,
+    };
     let stack_result = run_worklist(&cfg, &irmap, &stack_analyzer);
-    let stack_safe = check_stack(stack_result, &irmap, &stack_analyzer);
+    let stack_safe = check_stack(stack_result, &irmap, &stack_analyzer, &func_name).is_empty();
     assert!(stack_safe);
     println!("Checking Heap Safety");
     let heap_analyzer = HeapAnalyzer {
         metadata: metadata.clone(),
+        strategy: HeapStrategy::HeapPtrFirstArgWithGuards,
     };
     let heap_result = run_worklist(&cfg, &irmap, &heap_analyzer);
-    let heap_safe = check_heap(heap_result, &irmap, &heap_analyzer);
+    let heap_safe = check_heap(heap_result, &irmap, &heap_analyzer, &func_name).is_empty();
     assert!(heap_safe);
     println!("Checking Call Safety");
     if has_indirect_calls(&irmap) {
@@ -86,7 +70,15 @@ fn negative_test_helper(path: &str, func_name: &str) {
             funcs: vec![],
         };
         let call_result = run_worklist(&cfg, &irmap, &call_analyzer);
-        let call_safe = check_calls(call_result, &irmap, &call_analyzer, &valid_funcs, &plt);
+        let call_safe = check_calls(
+            call_result,
+            &irmap,
+            &call_analyzer,
+            &valid_funcs,
+            &plt,
+            &func_name,
+        )
+        .is_empty();
         assert!(call_safe);
     }
     println!("Done! ");
@@ -107,6 +99,27 @@ fn full_test_libogg() {
     full_test_helper("./veriwasm_public_data/firefox_libs/liboggwasm.so")
 }
 
+// The report with a different number of workers is the same, apart from
+// timings.
+#[test]
+fn parallel_report_test() {
+    let data = std::fs::read("./veriwasm_public_data/firefox_libs/liboggwasm.so").unwrap();
+    let report_json = |num_jobs| {
+        let config = VerifyConfig {
+            num_jobs,
+            ..VerifyConfig::default()
+        };
+        let mut report = verify_module(&data, &config).unwrap();
+        report.config.jobs = 0;
+        report.timings = Default::default();
+        for function in &mut report.functions {
+            function.timings = Default::default();
+        }
+        serde_json::to_string(&report).unwrap()
+    };
+    assert_eq!(report_json(1), report_json(4));
+}
+
 // #[test]
 // fn full_test_shootout() {
 //     full_test_helper("./veriwasm_data/shootout/shootout.so")
@@ -311,7 +324,7 @@ fn negative_test_11() {
 }
 
 #[test]
-#[should_panic(expected = "not implemented")]
+#[should_panic]
 fn negative_test_12() {
     negative_test_helper(
         "veriwasm_public_data/negative_tests/negative_tests.so",
@@ -320,7 +333,7 @@ fn negative_test_12() {
 }
 
 #[test]
-#[should_panic(expected = "not implemented")]
+#[should_panic]
 fn negative_test_13() {
     negative_test_helper(
         "veriwasm_public_data/negative_tests/negative_tests.so",
@@ -348,7 +361,7 @@ fn negative_test_nacl_23() {
 }
 
 #[test]
-#[should_panic(expected = "not implemented")]
+#[should_panic]
 fn negative_test_nacl_323_1() {
     negative_test_helper(
         "veriwasm_public_data/negative_tests/negative_tests.so",
@@ -357,7 +370,7 @@ fn negative_test_nacl_323_1() {
 }
 
 #[test]
-#[should_panic(expected = "not implemented")]
+#[should_panic]
 fn negative_test_nacl_323_2() {
     negative_test_helper(
         "veriwasm_public_data/negative_tests/negative_tests.so",
@@ -366,7 +379,7 @@ fn negative_test_nacl_323_2() {
 }
 
 #[test]
-#[should_panic(expected = "not implemented")]
+#[should_panic]
 fn negative_test_nacl_323_3() {
     negative_test_helper(
         "veriwasm_public_data/negative_tests/negative_tests.so",
@@ -384,7 +397,7 @@ fn negative_test_nacl_323_4() {
 }
 
 #[test]
-#[should_panic(expected = "not implemented")]
+#[should_panic]
 fn negative_test_nacl_390() {
     negative_test_helper(
         "veriwasm_public_data/negative_tests/negative_tests.so",
@@ -402,7 +415,7 @@ fn negative_test_nacl_1585() {
 }
 
 #[test]
-#[should_panic(expected = "not implemented")]
+#[should_panic]
 fn negative_test_nacl_2532() {
     negative_test_helper(
         "veriwasm_public_data/negative_tests/negative_tests.so",
@@ -436,3 +449,128 @@ fn negative_test_cranelift_805() {
         "guest_func_cranelift_805",
     );
 }
+
+#[test]
+fn load_error_test() {
+    use crate::utils::utils::LoadError;
+
+    match verify_module(b"not an elf", &VerifyConfig::default()) {
+        Err(LoadError::NotElf) => (),
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("loaded a non-ELF input"),
+    }
+
+    let mut profile = RuntimeProfile::lucet();
+    profile.symbols.lucet_tables = Some("no_such_symbol".to_string());
+    match load_metadata(
+        "./veriwasm_public_data/firefox_libs/libgraphitewasm.so",
+        &profile,
+    ) {
+        Err(err) => assert_eq!(err.to_string(), "missing symbol no_such_symbol"),
+        Ok(_) => panic!("found a symbol that isn't in the module"),
+    }
+
+    let data = std::fs::read("./veriwasm_public_data/firefox_libs/libgraphitewasm.so").unwrap();
+    let config = VerifyConfig {
+        only_func: Some("no_such_function".to_string()),
+        ..VerifyConfig::default()
+    };
+    match verify_module(&data, &config) {
+        Err(err) => assert_eq!(err.to_string(), "missing symbol no_such_function"),
+        Ok(_) => panic!("verified a function that isn't in the module"),
+    }
+}
+
+/// A section for `elf_object`: name, sh_type, sh_link, sh_info, sh_entsize
+/// and contents.
+#[cfg(test)]
+pub type TestSection<'a> = (&'a str, u32, u32, u32, u64, Vec<u8>);
+
+/// Builds a little-endian x86-64 ELF object holding the given sections,
+/// after the null section and followed by `.shstrtab`.
+#[cfg(test)]
+pub fn elf_object(e_type: u16, sections: &[TestSection]) -> Vec<u8> {
+    let mut shstrtab = vec![0u8];
+    let mut all: Vec<(u32, u32, u32, u32, u64, &[u8])> = vec![];
+    for (name, sh_type, link, info, entsize, data) in sections.iter() {
+        all.push((
+            shstrtab.len() as u32,
+            *sh_type,
+            *link,
+            *info,
+            *entsize,
+            &data[..],
+        ));
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+    }
+    all.push((shstrtab.len() as u32, 3, 0, 0, 0, &[]));
+    shstrtab.extend_from_slice(b".shstrtab\0");
+    let last = all.len() - 1;
+    all[last].5 = &shstrtab;
+
+    let mut body = vec![];
+    let mut headers = vec![0u8; 64];
+    for (name, sh_type, link, info, entsize, data) in all.iter() {
+        while body.len() % 8 != 0 {
+            body.push(0);
+        }
+        let offset = 64 + body.len() as u64;
+        body.extend_from_slice(data);
+        headers.extend_from_slice(&name.to_le_bytes());
+        headers.extend_from_slice(&sh_type.to_le_bytes());
+        headers.extend_from_slice(&0u64.to_le_bytes()); // sh_flags
+        headers.extend_from_slice(&0u64.to_le_bytes()); // sh_addr
+        headers.extend_from_slice(&offset.to_le_bytes());
+        headers.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        headers.extend_from_slice(&link.to_le_bytes());
+        headers.extend_from_slice(&info.to_le_bytes());
+        headers.extend_from_slice(&8u64.to_le_bytes()); // sh_addralign
+        headers.extend_from_slice(&entsize.to_le_bytes());
+    }
+    while body.len() % 8 != 0 {
+        body.push(0);
+    }
+    let shnum = all.len() as u16 + 1;
+
+    let mut elf = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    elf.extend_from_slice(&e_type.to_le_bytes());
+    elf.extend_from_slice(&62u16.to_le_bytes()); // EM_X86_64
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&0u64.to_le_bytes()); // e_entry
+    elf.extend_from_slice(&0u64.to_le_bytes()); // e_phoff
+    elf.extend_from_slice(&(64 + body.len() as u64).to_le_bytes());
+    elf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    for half in [64u16, 0, 0, 64, shnum, shnum - 1].iter() {
+        elf.extend_from_slice(&half.to_le_bytes());
+    }
+    elf.extend_from_slice(&body);
+    elf.extend_from_slice(&headers);
+    elf
+}
+
+/// Runs the stack analysis on `ir`, written in the `ir_text` syntax, with
+/// the probestack strategy, and returns the violations.
+#[cfg(test)]
+pub fn stack_violations(ir: &str) -> Vec<crate::checkers::Violation> {
+    let (cfg, irmap) = crate::utils::ir_text::parse_ir(ir).unwrap();
+    let analyzer = StackAnalyzer {
+        strategy: StackStrategy::ProbeStack,
+        entrypoint: cfg.entrypoint,
+    };
+    let result = run_worklist(&cfg, &irmap, &analyzer);
+    check_stack(result, &irmap, &analyzer, "f")
+}
+
+/// Runs the heap analysis on `ir`, written in the `ir_text` syntax, and
+/// returns the violations.
+#[cfg(test)]
+pub fn heap_violations(ir: &str, strategy: HeapStrategy) -> Vec<crate::checkers::Violation> {
+    let (cfg, irmap) = crate::utils::ir_text::parse_ir(ir).unwrap();
+    let analyzer = HeapAnalyzer {
+        metadata: Default::default(),
+        strategy,
+    };
+    let result = run_worklist(&cfg, &irmap, &analyzer);
+    check_heap(result, &irmap, &analyzer, "f")
+}
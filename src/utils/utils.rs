@@ -3,31 +3,153 @@ use crate::analyses::jump_analyzer::SwitchAnalyzer;
 use crate::analyses::reaching_defs::analyze_reaching_defs;
 use crate::analyses::reaching_defs::ReachingDefnAnalyzer;
 use crate::checkers::jump_resolver::resolve_jumps;
+use crate::profile::{GlobalsLayout, RuntimeProfile, TableLayout, VMCtxLayout};
+use crate::utils::cwasm::{cwasm_functions, CwasmFuncKind};
 use crate::utils::ir_utils::has_indirect_jumps;
 use crate::utils::lifter::{lift_cfg, IRMap, MemArg, MemArgs};
+use crate::utils::relocs::{is_relocatable, relocatable_module, ET_REL};
 use std::path::Path;
 use yaxpeax_arch::Arch;
 use yaxpeax_core::analyses::control_flow::{get_cfg, VW_CFG};
 use yaxpeax_core::arch::x86_64::x86_64Data;
 use yaxpeax_core::arch::x86_64::MergedContextTable;
+use yaxpeax_core::arch::InstructionSpan;
 use yaxpeax_core::arch::SymbolQuery;
 use yaxpeax_core::arch::{BaseUpdate, Library, Symbol};
 use yaxpeax_core::memory::repr::process::{
-    ELFExport, ELFImport, ELFSymbol, ModuleData, ModuleInfo,
+    ELFExport, ELFImport, ELFSection, ELFSymbol, ModuleData, ModuleInfo,
 };
 use yaxpeax_core::memory::repr::FileRepr;
 use yaxpeax_core::memory::MemoryRepr;
 use yaxpeax_core::ContextWrite;
 use yaxpeax_x86::long_mode::Arch as AMD64;
 
-pub fn load_program(binpath: &str) -> ModuleData {
-    let program = yaxpeax_core::memory::reader::load_from_path(Path::new(binpath)).unwrap();
-    let program = if let FileRepr::Executable(program) = program {
-        program
-    } else {
-        panic!("function:{} is not a valid path", binpath);
-    };
-    program
+/// Why a module could not be loaded. These are problems with the input,
+/// not verification failures.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    /// Not an ELF file yaxpeax can read.
+    NotElf,
+    MissingSection(String),
+    /// A runtime symbol named by the profile, or a requested function, is
+    /// not in the module.
+    MissingSymbol(String),
+    /// A relocatable object whose relocations could not be applied.
+    Link(String),
+    InvalidCwasm(String),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::NotElf => write!(f, "not an ELF binary"),
+            LoadError::MissingSection(name) => write!(f, "missing section {}", name),
+            LoadError::MissingSymbol(name) => write!(f, "missing symbol {}", name),
+            LoadError::Link(err) => write!(f, "unable to link object file: {}", err),
+            LoadError::InvalidCwasm(err) => write!(f, "invalid .cwasm: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(err: std::io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+pub fn load_program(binpath: &str) -> Result<ModuleData, LoadError> {
+    let data = std::fs::read(binpath)?;
+    if is_relocatable(&data) {
+        return relocatable_module(binpath, &data).map_err(LoadError::Link);
+    }
+    load_executable(Path::new(binpath))
+}
+
+/// Like `load_program`, for a module that is already in memory. `name` is
+/// only used to label the module.
+pub fn load_program_bytes(name: &str, data: &[u8]) -> Result<ModuleData, LoadError> {
+    if is_relocatable(data) {
+        return relocatable_module(name, data).map_err(LoadError::Link);
+    }
+    ModuleData::load_from(data, name.to_string()).ok_or(LoadError::NotElf)
+}
+
+fn load_executable(path: &Path) -> Result<ModuleData, LoadError> {
+    match yaxpeax_core::memory::reader::load_from_path(path) {
+        Some(FileRepr::Executable(program)) => Ok(program),
+        _ => Err(LoadError::NotElf),
+    }
+}
+
+struct ElfInfo<'a> {
+    is_relocatable: bool,
+    sections: &'a Vec<ELFSection>,
+    entrypoint: &'a u64,
+    imports: &'a Vec<ELFImport>,
+    exports: &'a Vec<ELFExport>,
+    symbols: &'a Vec<ELFSymbol>,
+}
+
+fn elf_info(program: &ModuleData) -> Result<ElfInfo<'_>, LoadError> {
+    match (program as &dyn MemoryRepr<<AMD64 as Arch>::Address>).module_info() {
+        Some(ModuleInfo::ELF(_, header, _, sections, entrypoint, _, imports, exports, symbols)) => {
+            Ok(ElfInfo {
+                is_relocatable: header.e_type == ET_REL,
+                sections,
+                entrypoint,
+                imports,
+                exports,
+                symbols,
+            })
+        }
+        _ => Err(LoadError::NotElf),
+    }
+}
+
+/// What function discovery puts in yaxpeax's context table, as plain data.
+/// The table isn't `Sync`, so worker threads each build one from these
+/// with `build_contexts` rather than rediscovering the module's functions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ContextEntry {
+    /// A symbol at an address, from another library if `imported`.
+    Symbol {
+        addr: u64,
+        name: String,
+        imported: bool,
+    },
+    FunctionHint(u64),
+}
+
+pub fn build_contexts(entries: &[ContextEntry]) -> x86_64Data {
+    let mut x86_64_data = x86_64Data::default();
+    for entry in entries {
+        match entry {
+            ContextEntry::Symbol {
+                addr,
+                name,
+                imported,
+            } => {
+                let library = if *imported {
+                    Library::Unknown
+                } else {
+                    Library::This
+                };
+                x86_64_data.contexts.put(
+                    *addr,
+                    BaseUpdate::DefineSymbol(Symbol(library, name.clone())),
+                );
+            }
+            ContextEntry::FunctionHint(addr) => x86_64_data.contexts.put(
+                *addr,
+                BaseUpdate::Specialized(yaxpeax_core::arch::x86_64::x86Update::FunctionHint),
+            ),
+        }
+    }
+    x86_64_data
 }
 
 fn get_function_starts(
@@ -36,47 +158,40 @@ fn get_function_starts(
     imports: &std::vec::Vec<ELFImport>,
     exports: &std::vec::Vec<ELFExport>,
     _text_section_idx: usize,
-) -> x86_64Data {
-    let mut x86_64_data = x86_64Data::default();
+) -> Vec<ContextEntry> {
+    let mut entries = vec![];
 
     // start queuing up places we expect to find functions
-    x86_64_data.contexts.put(
-        *entrypoint as u64,
-        BaseUpdate::Specialized(yaxpeax_core::arch::x86_64::x86Update::FunctionHint),
-    );
+    entries.push(ContextEntry::FunctionHint(*entrypoint as u64));
 
     // copy in symbols (not really necessary here)
     for sym in symbols {
-        x86_64_data.contexts.put(
-            sym.addr as u64,
-            BaseUpdate::DefineSymbol(Symbol(Library::This, sym.name.clone())),
-        );
+        entries.push(ContextEntry::Symbol {
+            addr: sym.addr as u64,
+            name: sym.name.clone(),
+            imported: false,
+        });
     }
 
     //All symbols in text section should be function starts
     for sym in symbols {
-        x86_64_data.contexts.put(
-            sym.addr as u64,
-            BaseUpdate::Specialized(yaxpeax_core::arch::x86_64::x86Update::FunctionHint),
-        );
+        entries.push(ContextEntry::FunctionHint(sym.addr as u64));
     }
 
     // and copy in names for imports
     for import in imports {
-        x86_64_data.contexts.put(
-            import.value as u64,
-            BaseUpdate::DefineSymbol(Symbol(Library::Unknown, import.name.clone())),
-        );
+        entries.push(ContextEntry::Symbol {
+            addr: import.value as u64,
+            name: import.name.clone(),
+            imported: true,
+        });
     }
 
     // exports are probably functions? hope for the best
     for export in exports {
-        x86_64_data.contexts.put(
-            export.addr as u64,
-            BaseUpdate::Specialized(yaxpeax_core::arch::x86_64::x86Update::FunctionHint),
-        );
+        entries.push(ContextEntry::FunctionHint(export.addr as u64));
     }
-    x86_64_data
+    entries
 }
 
 fn try_resolve_jumps(
@@ -87,9 +202,9 @@ fn try_resolve_jumps(
     irmap: &IRMap,
     _addr: u64,
 ) -> (VW_CFG, IRMap, i32, u32) {
-    println!("Performing a reaching defs pass");
+    log::debug!("Performing a reaching defs pass");
     let reaching_defs = analyze_reaching_defs(cfg, &irmap, metadata.clone());
-    println!("Performing a jump resolution pass");
+    log::debug!("Performing a jump resolution pass");
     let switch_analyzer = SwitchAnalyzer {
         metadata: metadata.clone(),
         reaching_defs: reaching_defs,
@@ -115,7 +230,7 @@ fn resolve_cfg(
     metadata: &LucetMetadata,
     orig_irmap: &IRMap,
     addr: u64,
-) -> (VW_CFG, IRMap) {
+) -> (VW_CFG, IRMap, u32) {
     let (mut cfg, mut irmap, mut resolved_switches, mut still_unresolved) =
         try_resolve_jumps(program, contexts, cfg, metadata, orig_irmap, addr);
     while still_unresolved != 0 {
@@ -124,62 +239,76 @@ fn resolve_cfg(
         cfg = new_cfg;
         irmap = new_irmap;
         if (new_resolved_switches == resolved_switches) && (new_still_unresolved != 0) {
-            panic!("Fixed Point Error");
+            // no progress, so the remaining jumps can't be resolved
+            still_unresolved = new_still_unresolved;
+            break;
         }
         resolved_switches = new_resolved_switches;
         still_unresolved = new_still_unresolved;
     }
     assert_eq!(cfg.graph.node_count(), irmap.keys().len());
-    assert_eq!(still_unresolved, 0);
-    (cfg, irmap)
+    (cfg, irmap, still_unresolved)
 }
 
-pub fn fully_resolved_cfg(
+/// Like `fully_resolved_cfg`, but returns the number of indirect jumps that
+/// could not be resolved rather than panicking. Unresolved jumps are left
+/// without successors in the CFG.
+pub fn partially_resolved_cfg(
     program: &ModuleData,
     contexts: &MergedContextTable,
     metadata: &LucetMetadata,
     addr: u64,
-) -> (VW_CFG, IRMap) {
+) -> (VW_CFG, IRMap, u32) {
     let (cfg, _) = get_cfg(program, contexts, addr, None);
     let irmap = lift_cfg(&program, &cfg, &metadata);
     if !has_indirect_jumps(&irmap) {
-        return (cfg, irmap);
+        return (cfg, irmap, 0);
     }
     return resolve_cfg(program, contexts, &cfg, metadata, &irmap, addr);
 }
 
+pub fn fully_resolved_cfg(
+    program: &ModuleData,
+    contexts: &MergedContextTable,
+    metadata: &LucetMetadata,
+    addr: u64,
+) -> (VW_CFG, IRMap) {
+    let (cfg, irmap, still_unresolved) = partially_resolved_cfg(program, contexts, metadata, addr);
+    if still_unresolved != 0 {
+        panic!("Fixed Point Error");
+    }
+    (cfg, irmap)
+}
+
 pub fn get_data(
-    binpath: &str,
     program: &ModuleData,
-) -> (x86_64Data, Vec<(u64, std::string::String)>, (u64, u64)) {
-    let (_, sections, entrypoint, imports, exports, symbols) =
-        match (program as &dyn MemoryRepr<<AMD64 as Arch>::Address>).module_info() {
-            Some(ModuleInfo::ELF(isa, _, _, sections, entry, _, imports, exports, symbols)) => {
-                (isa, sections, entry, imports, exports, symbols)
-            }
-            Some(other) => {
-                panic!("{:?} isn't an elf, but is a {:?}?", binpath, other);
-            }
-            None => {
-                panic!(
-                    "{:?} doesn't appear to be a binary yaxpeax understands.",
-                    binpath
-                );
-            }
-        };
+) -> Result<
+    (
+        Vec<ContextEntry>,
+        Vec<(u64, std::string::String)>,
+        (u64, u64),
+    ),
+    LoadError,
+> {
+    let ElfInfo {
+        sections,
+        entrypoint,
+        imports,
+        exports,
+        symbols,
+        ..
+    } = elf_info(program)?;
     // println!("Sections: {:?}", sections);
-    let plt_bounds = if let Some(plt_idx) = sections.iter().position(|x| x.name == ".plt") {
-        let plt = sections.get(plt_idx).unwrap();
-        (plt.start, plt.start + plt.size)
-    } else {
-        (0, 0)
+    let plt_bounds = match sections.iter().find(|x| x.name == ".plt") {
+        Some(plt) => (plt.start, plt.start + plt.size),
+        None => (0, 0),
     };
 
-    let text_section_idx = sections.iter().position(|x| x.name == ".text").unwrap();
-    let text_section = sections.get(text_section_idx).unwrap();
+    let text_section_idx = text_section_idx(sections)?;
+    let text_section = &sections[text_section_idx];
 
-    let mut x86_64_data =
-        get_function_starts(entrypoint, symbols, imports, exports, text_section_idx);
+    let mut entries = get_function_starts(entrypoint, symbols, imports, exports, text_section_idx);
+    let mut x86_64_data = build_contexts(&entries);
 
     let mut addrs: Vec<(u64, std::string::String)> = Vec::new();
     while let Some(addr) = x86_64_data.contexts.function_hints.pop() {
@@ -190,43 +319,92 @@ pub fn get_data(
             if is_valid_func_name(&symbol.1) {
                 addrs.push((addr, symbol.1.clone()));
             } else {
-                println!("Symbol = 0x{:x} {:?}", addr, symbol.1);
+                log::debug!("Symbol = 0x{:x} {:?}", addr, symbol.1);
             }
         }
     }
-    (x86_64_data, addrs, plt_bounds)
+    // the hints have all been used
+    entries.retain(|entry| match entry {
+        ContextEntry::FunctionHint(_) => false,
+        _ => true,
+    });
+    Ok((entries, addrs, plt_bounds))
 }
 
-pub fn get_one_resolved_cfg(binpath: &str, func: &str) -> ((VW_CFG, IRMap), x86_64Data) {
-    let program = load_program(binpath);
-    let metadata = load_metadata(binpath);
+fn text_section_idx(sections: &Vec<ELFSection>) -> Result<usize, LoadError> {
+    sections
+        .iter()
+        .position(|x| x.name == ".text")
+        .ok_or_else(|| LoadError::MissingSection(".text".to_string()))
+}
 
-    // grab some details from the binary and panic if it's not what we expected
-    let (_, sections, entrypoint, imports, exports, symbols) =
-        match (&program as &dyn MemoryRepr<<AMD64 as Arch>::Address>).module_info() {
-            Some(ModuleInfo::ELF(isa, _, _, sections, entry, _, imports, exports, symbols)) => {
-                (isa, sections, entry, imports, exports, symbols)
-            }
-            Some(other) => {
-                panic!("{:?} isn't an elf, but is a {:?}?", binpath, other);
+/// Like `get_data`, for a Wasmtime `.cwasm` artifact: function starts come
+/// from the artifact's Wasmtime symbols rather than from Lucet's, and
+/// trampolines are left out.
+pub fn get_cwasm_data(
+    data: &[u8],
+) -> Result<(Vec<ContextEntry>, Vec<(u64, String)>, (u64, u64)), LoadError> {
+    let funcs = cwasm_functions(data).map_err(LoadError::InvalidCwasm)?;
+
+    let mut entries = vec![];
+    let mut addrs: Vec<(u64, String)> = Vec::new();
+    for func in funcs {
+        entries.push(ContextEntry::Symbol {
+            addr: func.addr,
+            name: func.name.clone(),
+            imported: false,
+        });
+        match func.kind {
+            CwasmFuncKind::Wasm => {
+                entries.push(ContextEntry::FunctionHint(func.addr));
+                addrs.push((func.addr, func.name));
             }
-            None => {
-                panic!(
-                    "{:?} doesn't appear to be a binary yaxpeax understands.",
-                    binpath
-                );
+            CwasmFuncKind::Trampoline => {
+                log::debug!("Trampoline = 0x{:x} {:?}", func.addr, func.name);
             }
-        };
+        }
+    }
+    // Wasmtime calls imports through the vmctx rather than a PLT.
+    Ok((entries, addrs, (0, 0)))
+}
+
+pub fn get_one_resolved_cfg(
+    binpath: &str,
+    func: &str,
+) -> Result<((VW_CFG, IRMap), x86_64Data), LoadError> {
+    let program = load_program(binpath)?;
+    let metadata = load_metadata(binpath, &RuntimeProfile::lucet())?;
 
-    let text_section_idx = sections.iter().position(|x| x.name == ".text").unwrap();
-    let x86_64_data = get_function_starts(entrypoint, symbols, imports, exports, text_section_idx);
-    let addr = get_symbol_addr(symbols, func).unwrap();
+    let ElfInfo {
+        sections,
+        entrypoint,
+        imports,
+        exports,
+        symbols,
+        ..
+    } = elf_info(&program)?;
+    let text_section_idx = text_section_idx(sections)?;
+    let x86_64_data = build_contexts(&get_function_starts(
+        entrypoint,
+        symbols,
+        imports,
+        exports,
+        text_section_idx,
+    ));
+    let addr =
+        get_symbol_addr(symbols, func).ok_or_else(|| LoadError::MissingSymbol(func.to_string()))?;
     assert!(is_valid_func_name(&String::from(func)));
-    println!("Generating CFG for: {:?}", func);
-    return (
+    log::info!("Generating CFG for: {:?}", func);
+    Ok((
         fully_resolved_cfg(&program, &x86_64_data.contexts, &metadata, addr),
         x86_64_data,
-    );
+    ))
+}
+
+/// Disassembles the single instruction at `addr`, for diagnostics.
+pub fn disassemble_at(program: &ModuleData, addr: u64) -> Option<String> {
+    let mut iter = program.instructions_spanning(<AMD64 as Arch>::Decoder::default(), addr, addr);
+    iter.next().map(|(_, instr)| instr.to_string())
 }
 
 fn get_symbol_addr(symbols: &Vec<ELFSymbol>, name: &str) -> std::option::Option<u64> {
@@ -239,45 +417,57 @@ fn get_symbol_addr(symbols: &Vec<ELFSymbol>, name: &str) -> std::option::Option<
     x
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct LucetMetadata {
-    pub guest_table_0: u64,
-    pub lucet_tables: u64,
-    pub lucet_probestack: u64,
+    pub guest_table_0: Option<u64>,
+    pub lucet_tables: Option<u64>,
+    pub lucet_probestack: Option<u64>,
+    pub tables: TableLayout,
+    pub globals: GlobalsLayout,
+    pub vmctx: VMCtxLayout,
+}
+
+// A runtime symbol the profile names must be in the module, except in an
+// unlinked object, which only has the symbols its code refers to.
+fn lookup_profile_symbol(info: &ElfInfo, name: &Option<String>) -> Result<Option<u64>, LoadError> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    match get_symbol_addr(info.symbols, name) {
+        Some(addr) => Ok(Some(addr)),
+        None if info.is_relocatable => Ok(None),
+        None => Err(LoadError::MissingSymbol(name.clone())),
+    }
 }
 
-pub fn load_metadata(binpath: &str) -> LucetMetadata {
-    let program = load_program(binpath);
+pub fn load_metadata(binpath: &str, profile: &RuntimeProfile) -> Result<LucetMetadata, LoadError> {
+    get_metadata(&load_program(binpath)?, profile)
+}
 
-    // grab some details from the binary and panic if it's not what we expected
-    let (_, _sections, _entrypoint, _imports, _exports, symbols) =
-        match (&program as &dyn MemoryRepr<<AMD64 as Arch>::Address>).module_info() {
-            Some(ModuleInfo::ELF(isa, _, _, sections, entry, _, imports, exports, symbols)) => {
-                (isa, sections, entry, imports, exports, symbols)
-            }
-            Some(other) => {
-                panic!("{:?} isn't an elf, but is a {:?}?", binpath, other);
-            }
-            None => {
-                panic!(
-                    "{:?} doesn't appear to be a binary yaxpeax understands.",
-                    binpath
-                );
-            }
-        };
-
-    let guest_table_0 = get_symbol_addr(symbols, "guest_table_0").unwrap();
-    let lucet_tables = get_symbol_addr(symbols, "lucet_tables").unwrap();
-    let lucet_probestack = get_symbol_addr(symbols, "lucet_probestack").unwrap();
-    println!(
-        "guest_table_0 = {:x} lucet_tables = {:x} probestack = {:x}",
-        guest_table_0, lucet_tables, lucet_probestack
+pub fn get_metadata(
+    program: &ModuleData,
+    profile: &RuntimeProfile,
+) -> Result<LucetMetadata, LoadError> {
+    let info = elf_info(program)?;
+
+    let guest_table_0 = lookup_profile_symbol(&info, &profile.symbols.guest_table_0)?;
+    let lucet_tables = lookup_profile_symbol(&info, &profile.symbols.lucet_tables)?;
+    let lucet_probestack = lookup_profile_symbol(&info, &profile.symbols.probestack)?;
+    log::debug!(
+        "guest_table_0 = {:x?} lucet_tables = {:x?} probestack = {:x?}",
+        guest_table_0,
+        lucet_tables,
+        lucet_probestack
     );
-    LucetMetadata {
+    Ok(LucetMetadata {
         guest_table_0: guest_table_0,
         lucet_tables: lucet_tables,
         lucet_probestack: lucet_probestack,
-    }
+        tables: profile.tables,
+        globals: profile.globals,
+        vmctx: profile.vmctx,
+    })
 }
 
 pub fn get_rsp_offset(memargs: &MemArgs) -> Option<i64> {
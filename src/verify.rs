@@ -0,0 +1,599 @@
+//! The whole verification pipeline: load a module, run the jump, stack,
+//! heap and call checks on each of its functions, and collect the results
+//! into a `ModuleReport`. This is what `veriwasm` runs; `verify_module` is
+//! the entry point for embedding it.
+
+use crate::analyses::call_analyzer::CallAnalyzer;
+use crate::analyses::heap_analyzer::HeapAnalyzer;
+use crate::analyses::reaching_defs::{analyze_reaching_defs, ReachingDefnAnalyzer};
+use crate::analyses::run_worklist;
+use crate::analyses::stack_analyzer::StackAnalyzer;
+use crate::checkers::call_checker::check_calls;
+use crate::checkers::heap_checker::check_heap;
+use crate::checkers::jump_resolver::check_jumps;
+use crate::checkers::stack_checker::check_stack;
+use crate::checkers::Violation;
+use crate::profile::RuntimeProfile;
+use crate::report::{
+    Counts, FunctionReport, ModuleReport, ModuleSummary, PropertyVerdicts, ReportConfig,
+    ReportVerdict, Timings, ViolationReport, REPORT_VERSION,
+};
+use crate::utils::cwasm::is_cwasm;
+use crate::utils::dot::{cfg_to_dot, format_states};
+use crate::utils::ir_utils::{get_unknown_opcodes, has_indirect_calls};
+use crate::utils::utils::{
+    build_contexts, disassemble_at, get_cwasm_data, get_data, get_metadata, load_program_bytes,
+    partially_resolved_cfg, ContextEntry, LoadError, LucetMetadata,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+use yaxpeax_core::analyses::control_flow::check_cfg_integrity;
+use yaxpeax_core::arch::x86_64::{x86_64Data, MergedContextTable};
+use yaxpeax_core::memory::repr::process::ModuleData;
+
+#[derive(Clone, Debug)]
+pub struct VerifyConfig {
+    pub profile: RuntimeProfile,
+    /// Number of worker threads.
+    pub num_jobs: u32,
+    /// Verify only the function with this name.
+    pub only_func: Option<String>,
+}
+
+impl Default for VerifyConfig {
+    fn default() -> Self {
+        VerifyConfig {
+            profile: RuntimeProfile::lucet(),
+            num_jobs: 1,
+            only_func: None,
+        }
+    }
+}
+
+/// Which abstract state, if any, to label blocks with in a CFG dump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpState {
+    Heap,
+    Stack,
+    Call,
+}
+
+/// Asks for a Graphviz (DOT) rendering of each function's CFG.
+#[derive(Clone, Copy, Debug)]
+pub struct DotOptions {
+    pub state: Option<DumpState>,
+}
+
+pub enum Verdict {
+    Safe,
+    Unsafe(Vec<Violation>),
+    InternalError(String),
+}
+
+pub struct FunctionResult {
+    pub func_name: String,
+    pub num_blocks: usize,
+    pub num_instructions: usize,
+    pub timings: Timings,
+    /// Opcodes lifted by the lifter's generic fallback, one per occurrence.
+    pub unknown_opcodes: Vec<String>,
+    /// Disassembly of the instructions that violations point at.
+    pub disassembly: HashMap<u64, String>,
+    /// The function's CFG, if `DotOptions` were given.
+    pub dot: Option<String>,
+    pub verdict: Verdict,
+}
+
+pub struct LoadedModule {
+    pub program: ModuleData,
+    pub metadata: LucetMetadata,
+    pub profile: RuntimeProfile,
+    pub x86_64_data: x86_64Data,
+    /// What `x86_64_data` was built from.
+    pub context_entries: Vec<ContextEntry>,
+    pub func_addrs: Vec<(u64, String)>,
+    pub plt: (u64, u64),
+}
+
+impl LoadedModule {
+    pub fn valid_funcs(&self) -> Vec<u64> {
+        self.func_addrs.iter().map(|x| x.0).collect()
+    }
+
+    pub fn shared(&self) -> SharedModule<'_> {
+        SharedModule {
+            program: &self.program,
+            metadata: &self.metadata,
+            profile: &self.profile,
+            context_entries: &self.context_entries,
+            plt: self.plt,
+        }
+    }
+}
+
+/// The parts of a `LoadedModule` that worker threads share. yaxpeax's
+/// context table isn't `Sync`, so each worker builds its own from
+/// `context_entries`.
+#[derive(Clone, Copy)]
+pub struct SharedModule<'a> {
+    pub program: &'a ModuleData,
+    pub metadata: &'a LucetMetadata,
+    pub profile: &'a RuntimeProfile,
+    pub context_entries: &'a [ContextEntry],
+    pub plt: (u64, u64),
+}
+
+// The function starts of a module, as `load_module` finds them.
+fn load_function_starts(
+    program: &ModuleData,
+    data: &[u8],
+) -> Result<(Vec<ContextEntry>, Vec<(u64, String)>, (u64, u64)), LoadError> {
+    if is_cwasm(data) {
+        log::info!("Loading Wasmtime precompiled module");
+        get_cwasm_data(data)
+    } else {
+        get_data(program)
+    }
+}
+
+pub fn load_module(
+    name: &str,
+    data: &[u8],
+    profile: &RuntimeProfile,
+) -> Result<LoadedModule, LoadError> {
+    let program = load_program_bytes(name, data)?;
+    log::info!("Loading Metadata");
+    let metadata = get_metadata(&program, profile)?;
+    let (context_entries, func_addrs, plt) = load_function_starts(&program, data)?;
+    Ok(LoadedModule {
+        program,
+        metadata,
+        profile: profile.clone(),
+        x86_64_data: build_contexts(&context_entries),
+        context_entries,
+        func_addrs,
+        plt,
+    })
+}
+
+pub fn panic_message(payload: &Box<dyn std::any::Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+fn wants_state(dot: Option<DotOptions>, state: DumpState) -> bool {
+    dot.and_then(|dot| dot.state) == Some(state)
+}
+
+fn check_function_uncaught(
+    module: SharedModule,
+    contexts: &MergedContextTable,
+    valid_funcs: &Vec<u64>,
+    addr: u64,
+    func_name: &str,
+    dot: Option<DotOptions>,
+) -> FunctionResult {
+    let program = module.program;
+    let metadata = module.metadata;
+    log::debug!("Generating CFG for {:?}", func_name);
+    let start = Instant::now();
+    let (cfg, irmap, _) = partially_resolved_cfg(program, contexts, metadata, addr);
+    log::debug!("Analyzing: {:?}", func_name);
+    check_cfg_integrity(&cfg.blocks, &cfg.graph);
+    let unknown_opcodes = get_unknown_opcodes(&irmap)
+        .iter()
+        .map(|opcode| format!("{:?}", opcode))
+        .collect();
+    let mut violations = check_jumps(&cfg, &irmap, func_name);
+
+    let stack_start = Instant::now();
+    let stack_analyzer = StackAnalyzer {
+        strategy: module.profile.stack,
+        entrypoint: cfg.entrypoint,
+    };
+    let stack_result = run_worklist(&cfg, &irmap, &stack_analyzer);
+    let mut dump_states = None;
+    if wants_state(dot, DumpState::Stack) {
+        dump_states = Some(format_states(&stack_result));
+    }
+    violations.extend(check_stack(
+        stack_result,
+        &irmap,
+        &stack_analyzer,
+        func_name,
+    ));
+
+    log::debug!("Checking Heap Safety");
+    let heap_start = Instant::now();
+    let heap_analyzer = HeapAnalyzer {
+        metadata: metadata.clone(),
+        strategy: module.profile.heap.clone(),
+    };
+    let heap_result = run_worklist(&cfg, &irmap, &heap_analyzer);
+    if wants_state(dot, DumpState::Heap) {
+        dump_states = Some(format_states(&heap_result));
+    }
+    violations.extend(check_heap(heap_result, &irmap, &heap_analyzer, func_name));
+
+    let call_start = Instant::now();
+    log::debug!("Checking Call Safety");
+    if has_indirect_calls(&irmap) {
+        let reaching_defs = analyze_reaching_defs(&cfg, &irmap, metadata.clone());
+        let call_analyzer = CallAnalyzer {
+            metadata: metadata.clone(),
+            reaching_defs: reaching_defs.clone(),
+            reaching_analyzer: ReachingDefnAnalyzer {
+                cfg: cfg.clone(),
+                irmap: irmap.clone(),
+            },
+            funcs: valid_funcs.clone(),
+        };
+        let call_result = run_worklist(&cfg, &irmap, &call_analyzer);
+        if wants_state(dot, DumpState::Call) {
+            dump_states = Some(format_states(&call_result));
+        }
+        violations.extend(check_calls(
+            call_result,
+            &irmap,
+            &call_analyzer,
+            valid_funcs,
+            &module.plt,
+            func_name,
+        ));
+    }
+    let end = Instant::now();
+
+    let dot = dot.map(|_| {
+        let highlight: Vec<u64> = violations.iter().map(|v| v.addr).collect();
+        cfg_to_dot(func_name, &cfg, &irmap, dump_states.as_ref(), &highlight)
+    });
+    let disassembly = violations
+        .iter()
+        .filter_map(|v| disassemble_at(program, v.addr).map(|text| (v.addr, text)))
+        .collect();
+    let verdict = if violations.is_empty() {
+        Verdict::Safe
+    } else {
+        Verdict::Unsafe(violations)
+    };
+    FunctionResult {
+        func_name: func_name.to_string(),
+        num_blocks: cfg.blocks.len(),
+        num_instructions: irmap.values().map(|block| block.len()).sum(),
+        timings: Timings {
+            cfg_secs: (stack_start - start).as_secs_f64(),
+            stack_secs: (heap_start - stack_start).as_secs_f64(),
+            heap_secs: (call_start - heap_start).as_secs_f64(),
+            call_secs: (end - call_start).as_secs_f64(),
+        },
+        unknown_opcodes,
+        disassembly,
+        dot,
+        verdict,
+    }
+}
+
+/// Runs every check on the function at `addr`. A panic in the verifier is
+/// reported as `Verdict::InternalError` rather than unwinding.
+pub fn check_function(
+    module: SharedModule,
+    contexts: &MergedContextTable,
+    valid_funcs: &Vec<u64>,
+    addr: u64,
+    func_name: &str,
+    dot: Option<DotOptions>,
+) -> FunctionResult {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        check_function_uncaught(module, contexts, valid_funcs, addr, func_name, dot)
+    }));
+    match result {
+        Ok(result) => {
+            log::info!(
+                "Verified {:?} at {:?} blocks. CFG: {:?}s Stack: {:?}s Heap: {:?}s Calls: {:?}s",
+                result.func_name,
+                result.num_blocks,
+                result.timings.cfg_secs,
+                result.timings.stack_secs,
+                result.timings.heap_secs,
+                result.timings.call_secs
+            );
+            result
+        }
+        Err(payload) => internal_error(func_name, panic_message(&payload)),
+    }
+}
+
+fn internal_error(func_name: &str, msg: String) -> FunctionResult {
+    FunctionResult {
+        func_name: func_name.to_string(),
+        num_blocks: 0,
+        num_instructions: 0,
+        timings: Timings::default(),
+        unknown_opcodes: vec![],
+        disassembly: HashMap::new(),
+        dot: None,
+        verdict: Verdict::InternalError(msg),
+    }
+}
+
+/// Verifies the functions of `module` selected by `config`, on
+/// `config.num_jobs` threads. Results are in the order of the module's
+/// functions, regardless of which worker finished first. Fails if
+/// `config.only_func` names no function of the module.
+pub fn check_functions(
+    module: &LoadedModule,
+    config: &VerifyConfig,
+    dot: Option<DotOptions>,
+) -> Result<Vec<FunctionResult>, LoadError> {
+    let valid_funcs = module.valid_funcs();
+    let funcs: Vec<(u64, String)> = module
+        .func_addrs
+        .iter()
+        .filter(|(_, func_name)| match &config.only_func {
+            Some(only_func) => func_name == only_func,
+            None => true,
+        })
+        .cloned()
+        .collect();
+    if let (Some(only_func), true) = (&config.only_func, funcs.is_empty()) {
+        return Err(LoadError::MissingSymbol(only_func.clone()));
+    }
+    let shared = module.shared();
+    if config.num_jobs <= 1 {
+        return Ok(funcs
+            .iter()
+            .map(|(addr, func_name)| {
+                let contexts = &module.x86_64_data.contexts;
+                check_function(shared, contexts, &valid_funcs, *addr, func_name, dot)
+            })
+            .collect());
+    }
+
+    let num_workers = std::cmp::min(config.num_jobs as usize, funcs.len());
+    let next_func = AtomicUsize::new(0);
+    let mut results: Vec<(usize, FunctionResult)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..num_workers)
+            .map(|_| {
+                let (funcs, valid_funcs, next_func) = (&funcs, &valid_funcs, &next_func);
+                scope.spawn(move || {
+                    // The context table `load_module` built can't be shared,
+                    // so each worker builds a copy from its entries.
+                    let x86_64_data = build_contexts(shared.context_entries);
+                    let mut results = vec![];
+                    loop {
+                        let idx = next_func.fetch_add(1, Ordering::SeqCst);
+                        if idx >= funcs.len() {
+                            break;
+                        }
+                        let (addr, func_name) = &funcs[idx];
+                        let result = check_function(
+                            shared,
+                            &x86_64_data.contexts,
+                            valid_funcs,
+                            *addr,
+                            func_name,
+                            dot,
+                        );
+                        results.push((idx, result));
+                    }
+                    results
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| match worker.join() {
+                Ok(results) => results,
+                // workers catch panics, so this is unreachable
+                Err(payload) => panic::resume_unwind(payload),
+            })
+            .collect()
+    });
+    results.sort_by_key(|(idx, _)| *idx);
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+pub fn function_report(result: &FunctionResult) -> FunctionReport {
+    let (verdict, properties, error, violations) = match &result.verdict {
+        Verdict::Safe => (
+            ReportVerdict::Safe,
+            PropertyVerdicts::from_violations(&[]),
+            None,
+            vec![],
+        ),
+        Verdict::Unsafe(violations) => (
+            ReportVerdict::Unsafe,
+            PropertyVerdicts::from_violations(violations),
+            None,
+            violations
+                .iter()
+                .map(|v| ViolationReport {
+                    disassembly: result.disassembly.get(&v.addr).cloned(),
+                    ..ViolationReport::from(v)
+                })
+                .collect(),
+        ),
+        Verdict::InternalError(msg) => (
+            ReportVerdict::InternalError,
+            PropertyVerdicts::internal_error(),
+            Some(msg.clone()),
+            vec![],
+        ),
+    };
+    FunctionReport {
+        name: result.func_name.clone(),
+        verdict,
+        properties,
+        error,
+        violations,
+        num_blocks: result.num_blocks,
+        num_instructions: result.num_instructions,
+        unknown_opcodes: result.unknown_opcodes.clone(),
+        timings: result.timings.clone(),
+    }
+}
+
+/// Builds the report for a module from its functions' results. The
+/// module's path is left empty for the caller to fill in.
+pub fn module_report(
+    data: &[u8],
+    config: &VerifyConfig,
+    results: &[FunctionResult],
+) -> ModuleReport {
+    let sha256: String = Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let functions: Vec<FunctionReport> = results.iter().map(function_report).collect();
+    let mut counts = Counts {
+        functions: functions.len(),
+        ..Default::default()
+    };
+    let mut timings = Timings::default();
+    for function in &functions {
+        match function.verdict {
+            ReportVerdict::Safe => counts.safe += 1,
+            ReportVerdict::Unsafe => counts.unsafe_ += 1,
+            ReportVerdict::InternalError => counts.internal_errors += 1,
+        }
+        timings.add(&function.timings);
+    }
+    ModuleReport {
+        version: REPORT_VERSION,
+        verifier_version: env!("CARGO_PKG_VERSION").to_string(),
+        module: ModuleSummary {
+            path: String::new(),
+            sha256,
+        },
+        config: ReportConfig {
+            heap_strategy: format!("{:?}", config.profile.heap),
+            stack_strategy: format!("{:?}", config.profile.stack),
+            profile: config.profile.name.clone(),
+            jobs: config.num_jobs,
+            only_func: config.only_func.clone(),
+        },
+        functions,
+        counts,
+        timings,
+    }
+}
+
+/// Verifies every function of the module in `data`, which can be a linked
+/// binary, an unlinked object or a `.cwasm` artifact.
+pub fn verify_module(data: &[u8], config: &VerifyConfig) -> Result<ModuleReport, LoadError> {
+    let module = load_module("", data, &config.profile)?;
+    let results = check_functions(&module, config, None)?;
+    Ok(module_report(data, config, &results))
+}
+
+#[test]
+fn module_report_json_test() {
+    use crate::checkers::ViolationKind;
+    use crate::utils::lifter::Stmt;
+    use serde_json::json;
+
+    let result = |name: &str, verdict| FunctionResult {
+        func_name: name.to_string(),
+        num_blocks: 1,
+        num_instructions: 2,
+        timings: Timings::default(),
+        unknown_opcodes: vec![],
+        disassembly: vec![(0x12, "ret".to_string())].into_iter().collect(),
+        dot: None,
+        verdict,
+    };
+    let violation = Violation {
+        func: "bad".to_string(),
+        block_addr: 0x10,
+        addr: 0x12,
+        idx: 0,
+        stmt: Stmt::Ret,
+        operands: vec!["stackgrowth: -8".to_string()],
+        kind: ViolationKind::StackNotRestoredAtReturn,
+    };
+    let results = vec![
+        result("good", Verdict::Safe),
+        result("bad", Verdict::Unsafe(vec![violation])),
+        result("crash", Verdict::InternalError("oops".to_string())),
+    ];
+    let report = module_report(b"", &VerifyConfig::default(), &results);
+    let timings = json!({"cfg_secs": 0.0, "stack_secs": 0.0, "heap_secs": 0.0, "call_secs": 0.0});
+    let safe = json!({"stack": "safe", "heap": "safe", "call": "safe", "jump": "safe"});
+    let expected = json!({
+        "version": 1,
+        "verifier_version": env!("CARGO_PKG_VERSION"),
+        "module": {
+            "path": "",
+            "sha256": "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        },
+        "config": {
+            "profile": "lucet",
+            "heap_strategy": "HeapPtrFirstArgWithGuards",
+            "stack_strategy": "ProbeStack",
+            "jobs": 1,
+            "only_func": null,
+        },
+        "functions": [
+            {
+                "name": "good",
+                "verdict": "safe",
+                "properties": safe,
+                "error": null,
+                "violations": [],
+                "num_blocks": 1,
+                "num_instructions": 2,
+                "unknown_opcodes": [],
+                "timings": timings,
+            },
+            {
+                "name": "bad",
+                "verdict": "unsafe",
+                "properties": {"stack": "unsafe", "heap": "safe", "call": "safe", "jump": "safe"},
+                "error": null,
+                "violations": [{
+                    "property": "stack",
+                    "kind": "stack_not_restored_at_return",
+                    "description": "stack growth is not zero at return",
+                    "block_addr": 16,
+                    "addr": 18,
+                    "idx": 0,
+                    "stmt": "Ret",
+                    "operands": ["stackgrowth: -8"],
+                    "disassembly": "ret",
+                }],
+                "num_blocks": 1,
+                "num_instructions": 2,
+                "unknown_opcodes": [],
+                "timings": timings,
+            },
+            {
+                "name": "crash",
+                "verdict": "internal_error",
+                "properties": {
+                    "stack": "internal_error",
+                    "heap": "internal_error",
+                    "call": "internal_error",
+                    "jump": "internal_error",
+                },
+                "error": "oops",
+                "violations": [],
+                "num_blocks": 1,
+                "num_instructions": 2,
+                "unknown_opcodes": [],
+                "timings": timings,
+            },
+        ],
+        "counts": {"functions": 3, "safe": 1, "unsafe": 1, "internal_errors": 1},
+        "timings": timings,
+    });
+    assert_eq!(serde_json::to_value(&report).unwrap(), expected);
+}